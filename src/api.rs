@@ -0,0 +1,52 @@
+
+//! Json bodies sent to and received from tower.im.
+
+use serde::ser::{Serialize, SerializeMap, Serializer};
+
+/// Response of `GET /members/{uid}/weekly_reports/{year}-{week}/edit`.
+#[derive(Deserialize, Clone, Debug)]
+pub struct WeeklyEditResponse {
+    /// html of the weekly report form
+    pub html: String,
+}
+
+/// Response of `POST /members/{uid}/weekly_reports/{year}-{week}`.
+#[derive(Deserialize, Clone, Debug)]
+pub struct WeeklySubmitResponse {
+    pub success: bool,
+}
+
+/// Response of `POST /teams/{tid}/calendar_events/`.
+#[derive(Deserialize, Clone, Debug)]
+pub struct CalendarEventResponse {
+    pub success: bool,
+    /// path of the new event, missing when failed
+    #[serde(default)]
+    pub url: Option<String>,
+}
+
+/// Response of `POST /calendar_events/{guid}/comments`.
+#[derive(Deserialize, Clone, Debug)]
+pub struct CommentResponse {
+    pub success: bool,
+}
+
+/// Answer of one question in the `data` of a weekly report submission,
+/// encoded as `{"content": ..., "<name>": "<value>"}`.
+#[derive(Clone, Debug)]
+pub struct WeeklyAnswer<'a> {
+    /// form input name and value of the question
+    pub name: &'a str,
+    pub value: &'a str,
+    /// answer html
+    pub content: &'a str,
+}
+
+impl<'a> Serialize for WeeklyAnswer<'a> {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        let mut map = serializer.serialize_map(Some(2))?;
+        map.serialize_entry("content", self.content)?;
+        map.serialize_entry(self.name, self.value)?;
+        map.end()
+    }
+}
@@ -0,0 +1,61 @@
+
+extern crate env_logger;
+extern crate clap;
+extern crate tower;
+
+use std::fs::OpenOptions;
+use std::io::Write;
+use std::process::exit;
+
+use clap::{Arg, App};
+
+use tower::mock::{MockServer, REMEMBER_TOKEN, TEAM_GUID};
+
+fn main() {
+
+    let matches = App::new("tower-mock-server")
+                    .version("0.0.1")
+                    .about("Fake tower.im serving fixture pages, for testing without network")
+                    .arg(Arg::with_name("listen")
+                         .long("listen")
+                         .takes_value(true)
+                         .default_value("127.0.0.1:3000")
+                         .help("Address to listen on, use port 0 to pick a free one"))
+                    .arg(Arg::with_name("record")
+                         .long("record")
+                         .takes_value(true)
+                         .help("Append received requests to this file as json lines"))
+                    .get_matches();
+
+    env_logger::init().unwrap();
+
+    let log = match matches.value_of("record") {
+        Some(file) => {
+            match OpenOptions::new().create(true).append(true).open(file) {
+                Ok(f) => Some(Box::new(f) as Box<dyn Write + Send>),
+                Err(e) => {
+                    println!("open {} failed: {}", file, e);
+                    exit(1);
+                }
+            }
+        }
+        None => None,
+    };
+
+    let server = match MockServer::start(matches.value_of("listen").unwrap(), log) {
+        Ok(server) => server,
+        Err(e) => {
+            println!("start mock server failed: {}", e);
+            exit(1);
+        }
+    };
+
+    println!("mock tower.im listening on {}", server.url());
+    println!("try: TOWER_COOKIE=\"remember_token={}; remember_team_guid={}\" \
+              tower --base-url {} -w",
+             REMEMBER_TOKEN,
+             TEAM_GUID,
+             server.url());
+
+    server.join();
+}
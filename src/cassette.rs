@@ -0,0 +1,338 @@
+
+//! Record real tower.im traffic to cassette files and replay it later, so the
+//! scrapers can be checked against real markup without network.
+//!
+//! Cookies, csrf tokens, `conn_guid` and passwords are redacted before anything
+//! is written, but the pages themselves (member names, reports) are kept as is.
+
+use std::cell::RefCell;
+use std::fs::File;
+use std::io::{Read, Write};
+use std::path::{Path, PathBuf};
+
+use async_trait::async_trait;
+
+use regex::{Captures, Regex};
+
+use reqwest::header::{HeaderMap, HeaderName, HeaderValue};
+use reqwest::StatusCode;
+
+use url::Url;
+
+use crate::error::{Result, TowerError};
+use crate::transport::{Request, Response, Transport};
+
+const REDACTED: &str = "REDACTED";
+
+// headers carrying credentials, their values are dropped entirely
+const SECRET_HEADERS: &[&str] = &["authorization", "x-csrf-token"];
+
+// cookies kept as is, the team guid is in the recorded urls anyway and login
+// can't be replayed without it
+const PUBLIC_COOKIES: &[&str] = &["remember_team_guid"];
+
+/// A recorded request and the response to it.
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct Interaction {
+    pub method: String,
+    /// path with query string, so a cassette can be replayed against any host,
+    /// `conn_guid` in the query string is redacted
+    pub path: String,
+    pub request_headers: Vec<(String, String)>,
+    pub request_body: Option<String>,
+    pub status: u16,
+    /// path the response was finally served from
+    pub response_path: String,
+    pub response_headers: Vec<(String, String)>,
+    pub response_body: String,
+}
+
+/// A list of interactions, stored as json.
+#[derive(Serialize, Deserialize, Clone, Debug, Default)]
+pub struct Cassette {
+    pub interactions: Vec<Interaction>,
+}
+
+impl Cassette {
+    pub fn load<P: AsRef<Path>>(path: P) -> Result<Cassette> {
+        let mut content = String::new();
+        File::open(path.as_ref())?.read_to_string(&mut content)?;
+
+        serde_json::from_str(&content).map_err(|e| {
+            TowerError::Parse(format!("cassette {}: {}", path.as_ref().display(), e))
+        })
+    }
+
+    pub fn save<P: AsRef<Path>>(&self, path: P) -> Result<()> {
+        let content = serde_json::to_string_pretty(self)?;
+        File::create(path)?.write_all(content.as_bytes())?;
+
+        Ok(())
+    }
+}
+
+/// Send requests through another transport, and write every interaction to a
+/// cassette file.
+pub struct RecordingTransport<T: Transport> {
+    inner: T,
+    path: PathBuf,
+    cassette: RefCell<Cassette>,
+}
+
+impl<T: Transport> RecordingTransport<T> {
+    pub fn new<P: AsRef<Path>>(inner: T, path: P) -> RecordingTransport<T> {
+        RecordingTransport {
+            inner,
+            path: path.as_ref().to_owned(),
+            cassette: RefCell::new(Cassette::default()),
+        }
+    }
+}
+
+#[async_trait(?Send)]
+impl<T: Transport> Transport for RecordingTransport<T> {
+    async fn send(&self, request: &Request) -> Result<Response> {
+        let response = self.inner.send(request).await?;
+
+        let interaction = Interaction {
+            method: request.method.to_string(),
+            path: redact_query(&url_path(&request.url)),
+            request_headers: redact_headers(&request.headers),
+            request_body: request.body.as_ref().map(|x| redact_body(x)),
+            status: response.status.as_u16(),
+            response_path: redact_query(&url_path(&response.url)),
+            response_headers: redact_headers(&response.headers),
+            response_body: redact_body(&response.body),
+        };
+
+        // save after every request, so an aborted run still leaves a cassette
+        let mut cassette = self.cassette.borrow_mut();
+        cassette.interactions.push(interaction);
+        if let Err(e) = cassette.save(&self.path) {
+            warn!("save cassette {} failed: {}", self.path.display(), e);
+        }
+
+        Ok(response)
+    }
+}
+
+/// Answer requests from a cassette, without network.
+///
+/// Requests are matched by method, path and query string except `conn_guid`,
+/// which is redacted in the cassette, the interactions of
+/// the same request are replayed in recorded order, and the last one repeats
+/// once they run out. Unknown requests get `404 Not Found`.
+pub struct ReplayTransport {
+    interactions: Vec<Interaction>,
+    used: RefCell<Vec<bool>>,
+}
+
+impl ReplayTransport {
+    pub fn new(cassette: Cassette) -> ReplayTransport {
+        let used = vec![false; cassette.interactions.len()];
+
+        ReplayTransport {
+            interactions: cassette.interactions,
+            used: RefCell::new(used),
+        }
+    }
+
+    pub fn load<P: AsRef<Path>>(path: P) -> Result<ReplayTransport> {
+        Ok(ReplayTransport::new(Cassette::load(path)?))
+    }
+
+    fn find(&self, method: &str, path: &str) -> Option<&Interaction> {
+        let mut used = self.used.borrow_mut();
+        let mut last = None;
+
+        for (i, x) in self.interactions.iter().enumerate() {
+            if x.method != method || x.path != path {
+                continue;
+            }
+            if !used[i] {
+                used[i] = true;
+                return Some(x);
+            }
+            last = Some(x);
+        }
+
+        last
+    }
+}
+
+#[async_trait(?Send)]
+impl Transport for ReplayTransport {
+    async fn send(&self, request: &Request) -> Result<Response> {
+        let path = redact_query(&url_path(&request.url));
+        let method = request.method.to_string();
+
+        let interaction = match self.find(&method, &path) {
+            Some(x) => x,
+            None => {
+                warn!("no recorded response for {} {}", method, path);
+                return Ok(Response::new(StatusCode::NOT_FOUND, &request.url, ""));
+            }
+        };
+
+        let mut headers = HeaderMap::new();
+        for (name, value) in &interaction.response_headers {
+            match (HeaderName::from_bytes(name.as_bytes()), HeaderValue::from_str(value)) {
+                (Ok(name), Ok(value)) => {
+                    headers.append(name, value);
+                }
+                _ => warn!("ignore invalid header in cassette: {}: {}", name, value),
+            }
+        }
+        let status = StatusCode::from_u16(interaction.status)
+            .map_err(|_| TowerError::Parse(format!("status {} in cassette", interaction.status)))?;
+
+        Ok(Response {
+            status,
+            url: replace_path(&request.url, &interaction.response_path),
+            headers,
+            body: interaction.response_body.clone(),
+        })
+    }
+}
+
+// `https://tower.im/teams/?a=b` -> `/teams/?a=b`
+fn url_path(url: &str) -> String {
+    match Url::parse(url) {
+        Ok(url) => {
+            match url.query() {
+                Some(query) => format!("{}?{}", url.path(), query),
+                None => url.path().to_owned(),
+            }
+        }
+        Err(_) => url.to_owned(),
+    }
+}
+
+fn replace_path(url: &str, path: &str) -> String {
+    match Url::parse(url).and_then(|x| x.join(path)) {
+        Ok(url) => url.to_string(),
+        Err(_) => url.to_owned(),
+    }
+}
+
+// `/a?conn_guid=xxx&b=c` -> `/a?conn_guid=REDACTED&b=c`
+fn redact_query(path: &str) -> String {
+    let re = Regex::new(r"([?&]conn_guid=)[^&]*").unwrap();
+    re.replace_all(path, |caps: &Captures| format!("{}{}", &caps[1], REDACTED)).into_owned()
+}
+
+// keep cookie names but not their values
+fn redact_cookies(cookies: &str, separator: &str) -> String {
+    cookies.split(separator)
+        .map(|x| {
+            match x.find('=') {
+                Some(pos) if PUBLIC_COOKIES.contains(&&x[..pos]) => x.to_owned(),
+                Some(pos) => format!("{}={}", &x[..pos], REDACTED),
+                None => x.to_owned(),
+            }
+        })
+        .collect::<Vec<_>>()
+        .join(separator)
+}
+
+// names are lower case, every `Set-Cookie` is an entry of its own
+fn redact_headers(headers: &HeaderMap) -> Vec<(String, String)> {
+    let mut result = vec![];
+
+    for (name, value) in headers.iter() {
+        let name = name.as_str().to_owned();
+        let value = String::from_utf8_lossy(value.as_bytes()).into_owned();
+
+        if name == "set-cookie" {
+            // attributes like `path=/` are kept
+            let mut parts = value.splitn(2, ';');
+            let cookie = redact_cookies(parts.next().unwrap_or(""), "; ");
+            let value = match parts.next() {
+                Some(attrs) => format!("{};{}", cookie, attrs),
+                None => cookie,
+            };
+            result.push((name, value));
+        } else if name == "cookie" {
+            result.push((name, redact_cookies(&value, "; ")));
+        } else if SECRET_HEADERS.contains(&name.as_str()) {
+            result.push((name, REDACTED.to_owned()));
+        } else {
+            result.push((name, value));
+        }
+    }
+
+    result
+}
+
+// csrf tokens and `conn_guid` in pages and forms, and credentials in form
+// bodies
+fn redact_body(body: &str) -> String {
+    // tower.im puts `content` before `name`, accept both orders
+    let meta_re = Regex::new(concat!(r#"(content=")[^"]*("\s+name="csrf-token")"#,
+                                     r#"|(name="csrf-token"\s+content=")[^"]*"#))
+        .unwrap();
+    let input_re = Regex::new(r"<input\s[^>]*>").unwrap();
+    let value_re = Regex::new(r#"(\svalue=")[^"]*""#).unwrap();
+    let form_re = Regex::new(r"(^|&)(authenticity_token|conn_guid|email|password)=[^&]*")
+        .unwrap();
+
+    let body = meta_re.replace_all(body, |caps: &Captures| {
+        match caps.get(3) {
+            Some(prefix) => format!("{}{}", prefix.as_str(), REDACTED),
+            None => format!("{}{}{}", &caps[1], REDACTED, &caps[2]),
+        }
+    });
+    // hidden inputs of the sign in form and the members page, attributes in
+    // any order
+    let body = input_re.replace_all(&body, |caps: &Captures| {
+        let input = &caps[0];
+        if !input.contains(r#"name="authenticity_token""#) && !input.contains(r#"id="conn-guid""#) {
+            return input.to_owned();
+        }
+        value_re.replace_all(input, |caps: &Captures| format!("{}{}\"", &caps[1], REDACTED))
+            .into_owned()
+    });
+    let body = form_re.replace_all(&body, |caps: &Captures| {
+        format!("{}{}={}", &caps[1], &caps[2], REDACTED)
+    });
+
+    body.into_owned()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{redact_body, redact_query};
+
+    #[test]
+    fn csrf_tokens_in_pages() {
+        let page = concat!(r#"<meta content="token1" name="csrf-token" />"#,
+                           r#"<meta name="csrf-token" content="token2" />"#,
+                           r#"<input type="hidden" name="authenticity_token" value="token3" />"#,
+                           r#"<input value="token4" name="authenticity_token" type="hidden">"#,
+                           r#"<input type="hidden" id="conn-guid" value="guid1" />"#,
+                           r#"<input type="hidden" name="utf8" value="&#x2713;" />"#);
+
+        assert_eq!(redact_body(page),
+                   concat!(r#"<meta content="REDACTED" name="csrf-token" />"#,
+                           r#"<meta name="csrf-token" content="REDACTED" />"#,
+                           r#"<input type="hidden" name="authenticity_token" value="REDACTED" />"#,
+                           r#"<input value="REDACTED" name="authenticity_token" type="hidden">"#,
+                           r#"<input type="hidden" id="conn-guid" value="REDACTED" />"#,
+                           r#"<input type="hidden" name="utf8" value="&#x2713;" />"#));
+    }
+
+    #[test]
+    fn credentials_in_forms() {
+        assert_eq!(redact_body("authenticity_token=a%2Bb&email=x%40y&password=p&remember_me=1"),
+                   "authenticity_token=REDACTED&email=REDACTED&password=REDACTED&remember_me=1");
+        assert_eq!(redact_body("conn_guid=guid1&data=%5B%5D"), "conn_guid=REDACTED&data=%5B%5D");
+    }
+
+    #[test]
+    fn conn_guid_in_query() {
+        assert_eq!(redact_query("/members/a/weekly_reports/2017-18/?conn_guid=guid1&pjax=1"),
+                   "/members/a/weekly_reports/2017-18/?conn_guid=REDACTED&pjax=1");
+        assert_eq!(redact_query("/a/edit?conn_guid=guid1"), "/a/edit?conn_guid=REDACTED");
+        assert_eq!(redact_query("/a/?my_conn_guid=x"), "/a/?my_conn_guid=x");
+    }
+}
@@ -0,0 +1,1137 @@
+
+
+use reqwest::header::{HeaderMap, HeaderValue, ACCEPT, CONTENT_TYPE, COOKIE, LOCATION,
+                      SET_COOKIE, USER_AGENT};
+use reqwest::{Method, StatusCode};
+
+use time::{strftime, Tm};
+
+use tokio::time::sleep;
+
+use crate::api::{CalendarEventResponse, CommentResponse, WeeklyAnswer, WeeklyEditResponse,
+                 WeeklySubmitResponse};
+use crate::database::{CookieSource, SqliteCookie, SqliteType};
+use crate::error::{Result, TowerError};
+use crate::form::Form;
+use crate::markdown::to_markdown;
+use crate::session::{Cached, Session};
+use crate::parser;
+use crate::team::Team;
+use crate::transport::{is_transient, HttpTransport, Request, Response, RetryPolicy,
+                       RetryTransport, Transport};
+use crate::types::{CalendarEvent, Member, Overview, WeeklyField, WeeklyReport};
+use crate::week::Week;
+
+const X_CSRF_TOKEN: &str = "x-csrf-token";
+
+/// Client of tower.im, scraping the web pages with a logged in session.
+///
+/// Every request is async, `overview` fetches several pages at once.
+pub struct TowerClient {
+    transport: Box<dyn Transport>,
+    base_url: String,
+    tid: String,
+    uid: String,
+    conn_guid: String,
+    // user agent and accept, cookies and csrf token are added when sending
+    headers: HeaderMap,
+    // `name=value` pairs sent in `Cookie`
+    cookies: Vec<String>,
+    csrf_token: String,
+    members: Vec<Member>,
+    // `members` is scraped in this run, not restored from the session cache
+    members_fresh: bool,
+    // where fresh cookies are read from when the session expires
+    reauth_sources: Vec<Box<dyn CookieSource>>,
+    credentials: Option<(String, String)>,
+    retry: RetryPolicy,
+    // where cookies come from, cached sessions of other sources are ignored
+    session_source: String,
+    // `switch_team` was called, the session of that team is not cached so the
+    // default team of later runs stays the same
+    team_switched: bool,
+}
+
+impl TowerClient {
+    pub fn new() -> Result<TowerClient> {
+        let transport = RetryTransport::new(HttpTransport::new()?, RetryPolicy::default());
+        Ok(TowerClient::with_transport(Box::new(transport), "https://tower.im"))
+    }
+
+    /// Client sending requests through `transport` to the site at `base_url`,
+    /// like `https://tower.im`.
+    pub fn with_transport<T: AsRef<str>>(transport: Box<dyn Transport>,
+                                         base_url: T)
+                                         -> TowerClient {
+        TowerClient {
+            transport,
+            base_url: base_url.as_ref().trim_end_matches('/').to_owned(),
+            tid: String::new(),
+            uid: String::new(),
+            conn_guid: String::new(),
+            headers: HeaderMap::new(),
+            cookies: vec![],
+            csrf_token: String::new(),
+            members: Vec::with_capacity(200),
+            members_fresh: false,
+            reauth_sources: vec![],
+            credentials: None,
+            retry: RetryPolicy::default(),
+            session_source: String::new(),
+            team_switched: false,
+        }
+    }
+
+    pub fn base_url(&self) -> &str {
+        &self.base_url
+    }
+
+    /// Read cookies from `source` again when tower.im rejects our session,
+    /// sources are tried in the order they are added.
+    pub fn add_reauth_source(&mut self, source: Box<dyn CookieSource>) {
+        self.reauth_sources.push(source);
+    }
+
+    /// Retries of requests which can not be retried by the transport, like
+    /// submitting weekly reports.
+    pub fn set_retry_policy(&mut self, policy: RetryPolicy) {
+        self.retry = policy;
+    }
+
+    /// Where cookies of this run come from, like the cookie file of a browser
+    /// profile, so a session cached from another account is not used.
+    pub fn set_session_source<T: AsRef<str>>(&mut self, source: T) {
+        self.session_source = source.as_ref().to_owned();
+    }
+
+    /// Sign in with email and password when no cookie source works.
+    pub fn set_credentials<T: AsRef<str>>(&mut self, email: T, password: T) {
+        self.credentials = Some((email.as_ref().to_owned(), password.as_ref().to_owned()));
+    }
+
+    pub async fn load_sqlite<T: AsRef<str>>(&mut self,
+                                            file: T,
+                                            db_type: SqliteType,
+                                            chrome_key: Option<&str>)
+                                            -> Result<()> {
+
+        debug!("load sqlite from: {}", file.as_ref());
+
+        let mut sc = SqliteCookie::new(file, db_type);
+        if let Some(key) = chrome_key {
+            sc.set_chrome_key(key);
+        }
+
+        self.load_cookies(&mut sc).await
+    }
+
+    pub async fn load_cookies(&mut self, source: &mut dyn CookieSource) -> Result<()> {
+
+        source.read_data()?;
+
+        self.tid = source.team_id().clone();
+        let token = source.token().clone();
+
+        self.set_default_headers();
+        self.set_cookie(format!("remember_team_guid={}", self.tid));
+        self.set_cookie(format!("remember_token={}", token));
+
+        self.load_members_page().await
+    }
+
+    /// Sign in with email and password, no browser needed.
+    pub async fn login<T: AsRef<str>>(&mut self, email: T, password: T) -> Result<()> {
+
+        self.set_default_headers();
+        self.login_internal(email.as_ref(), password.as_ref()).await?;
+
+        self.load_members_page().await
+    }
+
+    async fn login_internal(&mut self, email: &str, password: &str) -> Result<()> {
+
+        // sign in page
+        let url = self.sign_in_url();
+        let response = self.send_once(Method::GET, &url, None).await?;
+        self.save_cookies(&response.headers);
+        let content = response.body;
+
+        let csrf_token = parser::csrf_token(&content, "sign in page")?;
+
+        // post email and password
+        let body = Form::new()
+            .append("authenticity_token", &csrf_token)
+            .append("email", email)
+            .append("password", password)
+            .append("remember_me", "1")
+            .finish();
+
+        // keep Set-Cookie of the redirect response
+        let mut request = Request::new(Method::POST, &url, self.request_headers());
+        request.headers.insert(CONTENT_TYPE, form_content_type());
+        request.body = Some(body);
+        request.follow_redirects = false;
+        let response = self.transport.send(&request).await?;
+        self.save_cookies(&response.headers);
+
+        if !self.has_cookie("remember_token") {
+            debug!("sign in response: {}", response.status);
+            return Err(TowerError::LoginFailed);
+        }
+
+        if self.tid.is_empty() {
+            // no team cookie yet, find the team from where we are redirected to
+            let location = match response.headers.get(LOCATION).and_then(|x| x.to_str().ok()) {
+                Some(location) => location.to_owned(),
+                None => "/".to_owned(),
+            };
+            let url = if location.starts_with('/') {
+                format!("{}{}", self.base_url, location)
+            } else {
+                location
+            };
+
+            let response = self.send_once(Method::GET, &url, None).await?;
+            self.save_cookies(&response.headers);
+            let content = response.body;
+
+            if self.tid.is_empty() {
+                self.tid = parser::team_guid(&content)?;
+            }
+        }
+
+        Ok(())
+    }
+
+    fn set_default_headers(&mut self) {
+
+        // `Host` is set by the transport from the url
+        let header_ua = HeaderValue::from_static("Mozilla/5.0 (X11; Linux x86_64; rv:51.0) \
+                                                  Gecko/20100101 Firefox/51.0");
+        self.headers.insert(USER_AGENT, header_ua);
+    }
+
+    // default headers with the session cookies and csrf token
+    fn request_headers(&self) -> HeaderMap {
+        let mut headers = self.headers.clone();
+
+        if !self.cookies.is_empty() {
+            match HeaderValue::from_str(&self.cookies.join("; ")) {
+                Ok(value) => {
+                    headers.insert(COOKIE, value);
+                }
+                Err(_) => warn!("invalid characters in cookies, not sent"),
+            }
+        }
+        if !self.csrf_token.is_empty() {
+            match HeaderValue::from_str(&self.csrf_token) {
+                Ok(value) => {
+                    headers.insert(X_CSRF_TOKEN, value);
+                }
+                Err(_) => warn!("invalid characters in csrf token, not sent"),
+            }
+        }
+
+        headers
+    }
+
+    // add or replace a `name=value` cookie
+    fn set_cookie<T: AsRef<str>>(&mut self, cookie: T) {
+        let cookie = cookie.as_ref();
+        let name = cookie.split('=').next().unwrap();
+
+        self.cookies.retain(|x| x.split('=').next() != Some(name));
+        self.cookies.push(cookie.to_owned());
+    }
+
+    fn has_cookie<T: AsRef<str>>(&self, name: T) -> bool {
+        self.cookie(name).is_some()
+    }
+
+    /// Value of cookie `name` we are sending.
+    pub fn cookie<T: AsRef<str>>(&self, name: T) -> Option<String> {
+        let prefix = format!("{}=", name.as_ref());
+        self.cookies
+            .iter()
+            .find(|x| x.starts_with(&prefix))
+            .map(|x| x[prefix.len()..].to_owned())
+    }
+
+    // remember the session cookies tower.im sends back
+    fn save_cookies(&mut self, headers: &HeaderMap) {
+        let set_cookie: Vec<String> = headers.get_all(SET_COOKIE)
+            .iter()
+            .map(|x| String::from_utf8_lossy(x.as_bytes()).into_owned())
+            .collect();
+
+        for cookie in set_cookie {
+            let i = cookie.split(';').next().unwrap().trim().to_owned();
+            let mut kv = i.splitn(2, '=');
+            let (name, value) = match (kv.next(), kv.next()) {
+                (Some(name), Some(value)) => (name.to_owned(), value.to_owned()),
+                _ => continue,
+            };
+
+            match name.as_str() {
+                "remember_team_guid" => self.tid = value,
+                "remember_token" | "_tower2_session" => {}
+                _ => continue,
+            }
+
+            info!("find cookie: {}", name);
+            self.set_cookie(i);
+        }
+    }
+
+    // scrape session info from the members page
+    async fn load_members_page(&mut self) -> Result<()> {
+
+        // members page
+        let url = self.members_url();
+        let response = self.send_once(Method::GET, &url, None).await?;
+
+        self.read_members_page(response)
+    }
+
+    fn read_members_page(&mut self, response: Response) -> Result<()> {
+
+        if login_required(&response) {
+            return Err(TowerError::SessionExpired);
+        }
+
+        // get extra cookies
+        self.save_cookies(&response.headers);
+        let content = response.body;
+
+        let page = parser::members_page(&content)?;
+        self.csrf_token = page.csrf_token;
+        self.conn_guid = page.conn_guid;
+        self.uid = page.uid;
+
+        for member in &page.members {
+            info!("got member: {} {}", member.nickname, member.guid);
+        }
+        self.members = page.members;
+        self.members_fresh = true;
+
+        Ok(())
+    }
+
+    /// Restore the session saved by `save_session`, without any request.
+    pub fn load_session(&mut self) -> bool {
+
+        let session = match Session::load() {
+            Some(session) => session,
+            None => return false,
+        };
+
+        if session.cookies.value.is_empty() || session.csrf_token.value.is_empty() {
+            return false;
+        }
+
+        // sessions of other sites, like a local mock server, are useless here
+        if session.base_url != self.base_url {
+            debug!("ignore session of {}", session.base_url);
+            return false;
+        }
+
+        // another browser or profile is asked for, which may be another account
+        if session.source != self.session_source {
+            debug!("ignore session from {}", session.source);
+            return false;
+        }
+
+        debug!("load session cached at {}", session.cookies.updated_at);
+
+        self.set_default_headers();
+        for cookie in &session.cookies.value {
+            self.set_cookie(cookie);
+        }
+        self.csrf_token = session.csrf_token.value;
+        self.conn_guid = session.conn_guid.value;
+        self.uid = session.uid.value;
+        self.tid = session.tid.value;
+        self.members = session.members.value;
+        self.members_fresh = false;
+
+        true
+    }
+
+    /// Cache the session for later runs, unless the team was switched.
+    pub fn save_session(&self) {
+
+        if self.team_switched {
+            debug!("not caching session of switched team {}", self.tid);
+            return;
+        }
+
+        let session = Session {
+            base_url: self.base_url.clone(),
+            source: self.session_source.clone(),
+            cookies: Cached::new(self.cookies.clone()),
+            csrf_token: Cached::new(self.csrf_token.clone()),
+            conn_guid: Cached::new(self.conn_guid.clone()),
+            uid: Cached::new(self.uid.clone()),
+            tid: Cached::new(self.tid.clone()),
+            members: Cached::new(self.members.clone()),
+        };
+
+        if let Err(e) = session.save() {
+            warn!("save session failed: {}", e);
+        }
+    }
+
+    // find a working session after server rejected ours
+    async fn reauthenticate(&mut self) -> Result<()> {
+
+        info!("session rejected by server, reload it");
+        let tid = self.tid.clone();
+
+        // only csrf token or conn_guid is outdated
+        match self.load_members_page().await {
+            Ok(_) => return self.reauthenticated(),
+            Err(e) => debug!("reload members page failed: {}", e),
+        }
+
+        // another run may have logged in already
+        let cookies = self.cookies.clone();
+        if self.load_session() && self.cookies != cookies {
+            match self.restore_team(&tid).await {
+                Ok(_) => return self.reauthenticated(),
+                Err(e) => debug!("cached session failed: {}", e),
+            }
+        }
+
+        let mut sources = std::mem::take(&mut self.reauth_sources);
+        let mut result = Err(TowerError::SessionExpired);
+        for source in sources.iter_mut() {
+            result = match self.load_cookies(source.as_mut()).await {
+                Ok(_) => self.restore_team(&tid).await,
+                Err(e) => Err(e),
+            };
+            match result {
+                Ok(_) => break,
+                Err(ref e) => debug!("reload cookies failed: {}", e),
+            }
+        }
+        self.reauth_sources = sources;
+        if result.is_ok() {
+            return self.reauthenticated();
+        }
+
+        if let Some((email, password)) = self.credentials.clone() {
+            let result = match self.login(email, password).await {
+                Ok(_) => self.restore_team(&tid).await,
+                Err(e) => Err(e),
+            };
+            match result {
+                Ok(_) => return self.reauthenticated(),
+                Err(e) => debug!("sign in failed: {}", e),
+            }
+        }
+
+        Session::remove();
+        Err(TowerError::SessionExpired)
+    }
+
+    fn reauthenticated(&mut self) -> Result<()> {
+        info!("session reloaded");
+        self.save_session();
+        Ok(())
+    }
+
+    // new cookies may belong to another team than the one we switched to
+    async fn restore_team(&mut self, tid: &str) -> Result<()> {
+        if tid.is_empty() || self.tid == tid {
+            return Ok(());
+        }
+
+        self.tid = tid.to_owned();
+        self.set_cookie(format!("remember_team_guid={}", tid));
+        self.load_members_page().await
+    }
+
+    /// All teams the account can access.
+    pub async fn teams(&mut self) -> Result<Vec<Team>> {
+
+        let content = self.get_data(&TowerClient::teams_url).await?;
+
+        parser::teams(content)
+    }
+
+    pub fn current_team(&self) -> &String {
+        &self.tid
+    }
+
+    /// Switch to team `name`, which is team name or guid.
+    pub async fn switch_team<T: AsRef<str>>(&mut self, name: T) -> Result<()> {
+
+        let name = name.as_ref();
+        if self.tid == name {
+            return Ok(());
+        }
+
+        let team = match self.teams().await?.into_iter().find(|x| x.matches(name)) {
+            Some(team) => team,
+            None => return Err(TowerError::TeamNotFound(name.to_owned())),
+        };
+
+        if team.guid == self.tid {
+            return Ok(());
+        }
+
+        info!("switch to team {} {}", team.name, team.guid);
+        self.tid = team.guid;
+        let cookie = format!("remember_team_guid={}", self.tid);
+        self.set_cookie(cookie);
+
+        // member guid and member list are different in every team
+        self.load_members_page().await?;
+        self.team_switched = true;
+
+        Ok(())
+    }
+
+    /// Members of current team.
+    pub fn members(&self) -> &[Member] {
+        &self.members
+    }
+
+    /// Find member by nickname, the member list is scraped again if it comes
+    /// from the session cache, so new teammates are found.
+    pub async fn member<T: AsRef<str>>(&mut self, nickname: T) -> Result<Member> {
+        let nickname = nickname.as_ref();
+        if let Some(member) = self.members.iter().find(|x| x.nickname == nickname) {
+            return Ok(member.clone());
+        }
+
+        if !self.members_fresh {
+            info!("member {} not in cached member list, reload it", nickname);
+            self.load_members_page().await?;
+            self.save_session();
+
+            if let Some(member) = self.members.iter().find(|x| x.nickname == nickname) {
+                return Ok(member.clone());
+            }
+        }
+
+        Err(TowerError::MemberNotFound(nickname.to_owned()))
+    }
+
+    /// Calendar of current member, raw content for now.
+    pub async fn calendar_events(&mut self) -> Result<String> {
+
+        self.get_data(&TowerClient::calendar_events_url).await
+    }
+
+    /// Weekly report of `week`.
+    pub async fn weekly_report(&mut self, week: Week) -> Result<WeeklyReport> {
+
+        let url = |c: &TowerClient| c.weekly_report_page_url(week);
+        let content = self.get_data(&url).await?;
+
+        weekly_report_of(week, content)
+    }
+
+    /// Weekly report of `week` and the calendar, fetched at the same time as
+    /// the members page, which keeps the member list fresh.
+    pub async fn overview(&mut self, week: Week) -> Result<Overview> {
+
+        // the other urls need the member guid
+        if self.uid.is_empty() {
+            self.load_members_page().await?;
+        }
+
+        let members_url = self.members_url();
+        let report_url = self.weekly_report_page_url(week);
+        let calendar_url = self.calendar_events_url();
+        let (members, report, calendar) =
+            tokio::join!(self.send_once(Method::GET, &members_url, None),
+                         self.send_once(Method::GET, &report_url, None),
+                         self.send_once(Method::GET, &calendar_url, None));
+        let (members, report, calendar) = (members?, report?, calendar?);
+
+        // log in again and fetch one by one, conn_guid in the urls may change
+        if is_rejected(&members) || is_rejected(&report) || is_rejected(&calendar) {
+            self.reauthenticate().await?;
+            return Ok(Overview {
+                report: self.weekly_report(week).await?,
+                calendar: self.calendar_events().await?,
+            });
+        }
+
+        self.read_members_page(members)?;
+
+        Ok(Overview {
+            report: weekly_report_of(week, check_status(report, &report_url)?)?,
+            calendar: check_status(calendar, &calendar_url)?,
+        })
+    }
+
+    /// Fields of the weekly report form of `week`.
+    pub async fn weekly_fields(&mut self, week: Week) -> Result<Vec<WeeklyField>> {
+
+        // get weekly info
+        let url = |c: &TowerClient| {
+            format!("{}/edit?conn_guid={}",
+                    c.weekly_report_url(week),
+                    c.conn_guid)
+        };
+
+        let result = self.get_data(&url).await?;
+        let form: WeeklyEditResponse = serde_json::from_str(&result)?;
+
+        parser::weekly_fields(form.html)
+    }
+
+    /// Submit `answers` of `fields`, answers are html.
+    pub async fn submit_weekly_report(&mut self,
+                                      week: Week,
+                                      fields: &[WeeklyField],
+                                      answers: &[String])
+                                      -> Result<()> {
+
+        let data: Vec<WeeklyAnswer> = fields.iter()
+            .zip(answers)
+            .map(|(field, ans)| {
+                WeeklyAnswer {
+                    name: &field.name,
+                    value: &field.value,
+                    content: ans,
+                }
+            })
+            .collect();
+
+        let data = serde_json::to_string(&data)?;
+        let request = |c: &TowerClient| {
+            let form = Form::new()
+                .append("conn_guid", &c.conn_guid)
+                .append("data", &data);
+            (c.weekly_report_url(week), form)
+        };
+
+        self.headers.insert(ACCEPT,
+                            HeaderValue::from_static("application/json, text/javascript, */*; \
+                                                      q=0.01"));
+
+        let mut attempt = 0;
+        let result = loop {
+            match self.post_form(&request).await {
+                Ok(result) => break result,
+                Err(ref e) if is_transient(e) && attempt < self.retry.retries => {
+                    warn!("submit weekly report failed: {}", e);
+                }
+                Err(e) => return Err(e),
+            }
+
+            sleep(self.retry.delay(attempt)).await;
+            attempt += 1;
+
+            // the report may be saved though we did not get the response
+            if self.weekly_report_landed(week, answers).await {
+                info!("weekly report was submitted already");
+                return Ok(());
+            }
+        };
+        let response: WeeklySubmitResponse = serde_json::from_str(&result)?;
+
+        if !response.success {
+            debug!("{}", result);
+            return Err(TowerError::Rejected(result));
+        }
+
+        Ok(())
+    }
+
+    // whether the report of `week` holds `answers` already, html is compared
+    // by text as tower.im serializes it its own way, like `<br>`
+    async fn weekly_report_landed(&mut self, week: Week, answers: &[String]) -> bool {
+        let contents = match self.weekly_report(week).await {
+            Ok(report) => report.contents(),
+            Err(e) => {
+                debug!("check weekly report failed: {}", e);
+                return false;
+            }
+        };
+
+        !answers.is_empty() &&
+        answers.iter()
+            .enumerate()
+            .all(|(i, ans)| contents.get(i).is_some_and(|x| to_markdown(x) == to_markdown(ans)))
+    }
+
+    /// Create an event in team calendar.
+    ///
+    /// Not retried, as a lost response can't be told from a failed request.
+    pub async fn create_calendar_event<T: AsRef<str>>(&mut self,
+                                                      title: T,
+                                                      starts_at: &Tm,
+                                                      ends_at: &Tm)
+                                                      -> Result<CalendarEvent> {
+
+        let time_format = "%Y-%m-%d %H:%M:%S";
+        let start_time = strftime(time_format, starts_at).unwrap();
+        let end_time = strftime(time_format, ends_at).unwrap();
+        let request = |c: &TowerClient| {
+            let form = Form::new()
+                .append("conn_guid", &c.conn_guid)
+                .append("content", title.as_ref())
+                .append("starts_at", &start_time)
+                .append("ends_at", &end_time)
+                .append("is_show_creator", "true")
+                .append("caleventable_type", "Calendar")
+                .append("caleventable_guid", "b96e5a357a884c7e8c5c2ab12858dd02")
+                .append("schedule_interval", "1");
+            (c.team_calendar_events_url(), form)
+        };
+
+        // post data and check result
+        let response = self.post_form(&request).await?;
+        let result: CalendarEventResponse = serde_json::from_str(&response)?;
+
+        if !result.success {
+            return Err(TowerError::Rejected(response));
+        }
+
+        match result.url {
+            Some(url) => Ok(CalendarEvent { url }),
+            None => Err(TowerError::Parse("url of calendar event".to_owned())),
+        }
+    }
+
+    /// Comment on `url`, like the url of a calendar event, and cc `members`.
+    pub async fn create_comment<T: AsRef<str>>(&mut self,
+                                               url: T,
+                                               content: T,
+                                               members: &[Member])
+                                               -> Result<()> {
+
+        let cc_guids: Vec<&str> = members.iter().map(|x| x.guid.as_str()).collect();
+        let request = |c: &TowerClient| {
+            let form = Form::new()
+                .append("conn_guid", &c.conn_guid)
+                .append("comment_content", content.as_ref())
+                .append("is_html", "1")
+                .append("cc_guids", cc_guids.join(","));
+            (format!("{}{}/comments", c.base_url, url.as_ref()), form)
+        };
+
+        let response = self.post_form(&request).await?;
+        let result: CommentResponse = serde_json::from_str(&response)?;
+
+        if !result.success {
+            return Err(TowerError::Rejected(response));
+        }
+
+        Ok(())
+    }
+
+    // requests are built from current session state by `request`, so they can
+    // be built again after logging in again
+    async fn post_form(&mut self,
+                       request: &dyn Fn(&TowerClient) -> (String, Form))
+                       -> Result<String> {
+        let request = |c: &TowerClient| {
+            let (url, form) = request(c);
+            (url, Some(form.finish()))
+        };
+        self.send_data(Method::POST, &request).await
+    }
+
+    async fn get_data(&mut self, url: &dyn Fn(&TowerClient) -> String) -> Result<String> {
+        self.send_data(Method::GET, &|c: &TowerClient| (url(c), None)).await
+    }
+
+    // send request, log in again and retry once if server rejected it
+    async fn send_data(&mut self,
+                       method: Method,
+                       request: &dyn Fn(&TowerClient) -> (String, Option<String>))
+                       -> Result<String> {
+        let (url, body) = request(self);
+        let response = self.send_once(method.clone(), &url, body.as_deref())
+            .await?;
+        if !is_rejected(&response) {
+            return check_status(response, &url);
+        }
+
+        self.reauthenticate().await?;
+
+        // conn_guid and member guid may be changed after reload
+        let (url, body) = request(self);
+        let response = self.send_once(method, &url, body.as_deref()).await?;
+        if is_rejected(&response) {
+            Session::remove();
+            return Err(TowerError::SessionExpired);
+        }
+
+        check_status(response, &url)
+    }
+
+    async fn send_once(&self, method: Method, url: &str, body: Option<&str>) -> Result<Response> {
+        let mut request = Request::new(method, url, self.request_headers());
+        if let Some(body) = body {
+            // every body we send is built by `Form`
+            request.headers.insert(CONTENT_TYPE, form_content_type());
+            request.body = Some(body.to_owned());
+        }
+
+        self.transport.send(&request).await
+    }
+
+    // the site pads week numbers, like `2017-05`
+    fn weekly_report_url(&self, week: Week) -> String {
+        format!("{}/members/{}/weekly_reports/{}-{}",
+                self.base_url,
+                self.uid,
+                week.year,
+                week.number())
+    }
+
+    fn weekly_report_page_url(&self, week: Week) -> String {
+        format!("{}/?conn_guid={}&pjax=1",
+                self.weekly_report_url(week),
+                self.conn_guid)
+    }
+
+    fn calendar_events_url(&self) -> String {
+        format!("{}/members/{}/calendar_events/", self.base_url, self.uid)
+    }
+
+    fn team_calendar_events_url(&self) -> String {
+        format!("{}/teams/{}/calendar_events/", self.base_url, self.tid)
+    }
+
+    fn sign_in_url(&self) -> String {
+        format!("{}/users/sign_in", self.base_url)
+    }
+
+    // fn profile_url<T: AsRef<str>>(&self, uid: T) -> String {
+    //     format!("https://tower.im/members/{}/?me=1", uid.as_ref())
+    // }
+
+    fn members_url(&self) -> String {
+        format!("{}/teams/{}/members/", self.base_url, self.tid)
+    }
+
+    fn teams_url(&self) -> String {
+        format!("{}/teams/", self.base_url)
+    }
+}
+
+// tower.im wants us to sign in again, by status, redirect or the sign in form
+fn login_required(response: &Response) -> bool {
+    if response.status == StatusCode::UNAUTHORIZED || response.status == StatusCode::FORBIDDEN {
+        return true;
+    }
+
+    if response.status.is_redirection() {
+        if let Some(location) = response.headers.get(LOCATION).and_then(|x| x.to_str().ok()) {
+            if location.contains("/users/sign_in") {
+                return true;
+            }
+        }
+    }
+
+    response.path() == "/users/sign_in" || response.body.contains(r#"action="/users/sign_in""#)
+}
+
+// 422 is an outdated csrf token
+fn is_rejected(response: &Response) -> bool {
+    login_required(response) || response.status == StatusCode::UNPROCESSABLE_ENTITY
+}
+
+fn check_status(response: Response, url: &str) -> Result<String> {
+    if response.status != StatusCode::OK {
+        debug!("{}", response.body);
+        return Err(TowerError::Status(response.status, url.to_owned()));
+    }
+
+    Ok(response.body)
+}
+
+fn form_content_type() -> HeaderValue {
+    HeaderValue::from_static("application/x-www-form-urlencoded")
+}
+
+fn weekly_report_of(week: Week, content: String) -> Result<WeeklyReport> {
+    Ok(WeeklyReport {
+        year: week.year,
+        week: week.number(),
+        sections: parser::weekly_report_sections(content)?,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use std::env::{set_var, temp_dir};
+    use std::process;
+    use std::rc::Rc;
+    use std::time::Duration;
+
+    use reqwest::{Method, StatusCode};
+    use reqwest::header::{CONTENT_TYPE, COOKIE};
+
+    use crate::database::HeaderCookie;
+    use crate::error::TowerError;
+    use crate::transport::{FakeTransport, Response, RetryPolicy};
+    use crate::types::WeeklyField;
+    use crate::week::Week;
+
+    use super::TowerClient;
+
+    const REPORT_URL: &str = "/members/mockmember0001/weekly_reports/2017-18";
+    const MEMBERS_URL: &str = "/teams/mockteam0001/members/";
+
+    // pages of the mock server
+    fn fixtures() -> FakeTransport {
+        FakeTransport::new()
+            .route(Method::GET,
+                   MEMBERS_URL,
+                   StatusCode::OK,
+                   include_str!("mock/fixtures/members.html"))
+            .route(Method::GET,
+                   format!("{}/", REPORT_URL),
+                   StatusCode::OK,
+                   include_str!("mock/fixtures/weekly_reports.html"))
+            .route(Method::GET,
+                   format!("{}/edit", REPORT_URL),
+                   StatusCode::OK,
+                   include_str!("mock/fixtures/weekly_edit.json"))
+            .route(Method::POST,
+                   REPORT_URL,
+                   StatusCode::OK,
+                   include_str!("mock/fixtures/weekly_submit.json"))
+            .route(Method::POST,
+                   "/teams/mockteam0001/calendar_events/",
+                   StatusCode::OK,
+                   include_str!("mock/fixtures/calendar_event_create.json"))
+            .route(Method::POST,
+                   "/calendar_events/mockevent0001/comments",
+                   StatusCode::OK,
+                   include_str!("mock/fixtures/comment_create.json"))
+    }
+
+    async fn signed_in(transport: &Rc<FakeTransport>) -> TowerClient {
+        let mut client = TowerClient::with_transport(Box::new(transport.clone()),
+                                                     "https://tower.im");
+        let mut cookies = HeaderCookie::new("remember_token=token; \
+                                             remember_team_guid=mockteam0001");
+        client.load_cookies(&mut cookies).await.unwrap();
+
+        client
+    }
+
+    fn client(transport: &Rc<FakeTransport>) -> TowerClient {
+        let mut client = TowerClient::with_transport(Box::new(transport.clone()),
+                                                     "https://tower.im");
+        client.uid = "mockmember0001".to_owned();
+        client.conn_guid = "mockconnguid0001".to_owned();
+        client.set_retry_policy(RetryPolicy {
+            retries: 1,
+            backoff: Duration::from_millis(1),
+        });
+
+        client
+    }
+
+    fn week() -> Week {
+        Week::new(2017, 18).unwrap()
+    }
+
+    fn field() -> Vec<WeeklyField> {
+        vec![WeeklyField {
+                 name: "question_guid".to_owned(),
+                 value: "mockquestion0001".to_owned(),
+                 title: "本周完成的工作".to_owned(),
+             }]
+    }
+
+    // the submit response is lost, but the report is saved
+    fn lost_submit(saved: &str) -> Rc<FakeTransport> {
+        let page = format!("<dl><dt>本周完成的工作</dt><dd class=\"editor-style\">{}</dd></dl>", saved);
+        Rc::new(FakeTransport::new()
+            .route(Method::POST, REPORT_URL, StatusCode::SERVICE_UNAVAILABLE, "")
+            .route(Method::GET, format!("{}/", REPORT_URL), StatusCode::OK, page))
+    }
+
+    #[tokio::test]
+    async fn submitted_report_landed() {
+        let transport = lost_submit("<p>a<br>\"b\"</p>");
+        let answers = vec!["<p>a<br/>&quot;b&quot;</p>".to_owned()];
+        client(&transport).submit_weekly_report(week(), &field(), &answers).await.unwrap();
+
+        let methods: Vec<Method> = transport.requests().into_iter().map(|x| x.method).collect();
+        assert_eq!(methods, vec![Method::POST, Method::GET]);
+    }
+
+    #[tokio::test]
+    async fn submitted_report_not_landed() {
+        let transport = lost_submit("<p>older</p>");
+        let answers = vec!["<p>newer</p>".to_owned()];
+        match client(&transport).submit_weekly_report(week(), &field(), &answers).await {
+            Err(TowerError::Status(StatusCode::SERVICE_UNAVAILABLE, _)) => {}
+            r => panic!("{:?}", r.map(|_| ())),
+        }
+    }
+
+    #[tokio::test]
+    async fn scrape_members_page() {
+        let transport = Rc::new(fixtures());
+        let mut client = signed_in(&transport).await;
+
+        assert_eq!(client.uid, "mockmember0001");
+        assert_eq!(client.conn_guid, "mockconnguid0001");
+        assert_eq!(client.csrf_token, "mock-csrf-token");
+        let nicknames: Vec<&str> = client.members().iter().map(|x| x.nickname.as_str()).collect();
+        assert_eq!(nicknames, vec!["sbw", "张三", "Alice & Bob"]);
+        assert_eq!(client.member("张三").await.unwrap().guid, "mockmember0002");
+
+        let requests = transport.requests();
+        assert_eq!(requests.len(), 1);
+        assert_eq!(requests[0].url, "https://tower.im/teams/mockteam0001/members/");
+        assert_eq!(requests[0].headers[COOKIE],
+                   "remember_team_guid=mockteam0001; remember_token=token");
+    }
+
+    #[tokio::test]
+    async fn weekly_report_and_fields() {
+        let transport = Rc::new(fixtures());
+        let mut client = signed_in(&transport).await;
+
+        let report = client.weekly_report(week()).await.unwrap();
+        let titles: Vec<&str> = report.sections.iter().map(|x| x.title.as_str()).collect();
+        assert_eq!(titles, vec!["本周完成的工作", "下周计划", "需要的帮助"]);
+        assert_eq!(report.sections[1].content, "<p>写测试 &amp; 修 bug</p>");
+        assert_eq!(report.sections[2].content, "");
+
+        let fields = client.weekly_fields(week()).await.unwrap();
+        let values: Vec<&str> = fields.iter().map(|x| x.value.as_str()).collect();
+        assert_eq!(values, vec!["mockquestion0001", "mockquestion0002", "mockquestion0003"]);
+        assert_eq!(fields[1].title, "下周计划");
+
+        let answers = vec!["<p>a</p>".to_owned(), "<p>b</p>".to_owned(), String::new()];
+        client.submit_weekly_report(week(), &fields, &answers).await.unwrap();
+
+        let post = transport.requests().pop().unwrap();
+        assert_eq!(post.method, Method::POST);
+        assert_eq!(post.headers["x-csrf-token"], "mock-csrf-token");
+        assert_eq!(post.headers[CONTENT_TYPE], "application/x-www-form-urlencoded");
+        assert!(post.body.unwrap().starts_with("conn_guid=mockconnguid0001&data=%5B%7B"));
+    }
+
+    #[tokio::test]
+    async fn overtime_event_with_comment() {
+        let transport = Rc::new(fixtures());
+        let mut client = signed_in(&transport).await;
+
+        let tm = time::now();
+        let event = client.create_calendar_event("加班登记", &tm, &tm).await.unwrap();
+        assert_eq!(event.url, "/calendar_events/mockevent0001");
+
+        let cc = client.member("张三").await.unwrap();
+        let content = format!("<p>{}</p>", cc.mention_html());
+        client.create_comment(event.url.as_str(), content.as_str(), &[cc]).await.unwrap();
+
+        let requests = transport.requests();
+        let event_body = requests[requests.len() - 2].body.clone().unwrap();
+        assert!(event_body.contains("&content=%E5%8A%A0%E7%8F%AD%E7%99%BB%E8%AE%B0&"));
+        let comment_body = requests[requests.len() - 1].body.clone().unwrap();
+        assert!(comment_body.ends_with("&is_html=1&cc_guids=mockmember0002"));
+    }
+
+    // a client whose cached session is outdated, tower.im sends it to the sign
+    // in page until the members page is loaded again
+    fn stale_client(transport: &Rc<FakeTransport>) -> TowerClient {
+        // sessions are cached after logging in again, keep them out of ~/.cache
+        set_var("XDG_CACHE_HOME", temp_dir().join(format!("tower-test-{}", process::id())));
+
+        let mut client = TowerClient::with_transport(Box::new(transport.clone()),
+                                                     "https://tower.im");
+        client.tid = "mockteam0001".to_owned();
+        client.uid = "stalemember".to_owned();
+        client.set_cookie("remember_token=token");
+
+        client
+    }
+
+    fn sign_in_page() -> Response {
+        Response::new(StatusCode::OK, "https://tower.im/users/sign_in", "")
+    }
+
+    #[tokio::test]
+    async fn rebuild_request_after_reload() {
+        let transport = fixtures()
+            .route_response(Method::GET,
+                            "/members/stalemember/weekly_reports/2017-18/edit",
+                            sign_in_page())
+            .route_response(Method::POST,
+                            "/members/stalemember/weekly_reports/2017-18",
+                            sign_in_page());
+        let transport = Rc::new(transport);
+        let mut client = stale_client(&transport);
+
+        // no conn_guid at all before the reload
+        let fields = client.weekly_fields(week()).await.unwrap();
+        assert_eq!(fields.len(), 3);
+
+        let urls: Vec<String> = transport.requests().into_iter().map(|x| x.url).collect();
+        assert_eq!(urls,
+                   vec!["https://tower.im/members/stalemember/weekly_reports/2017-18/edit?\
+                         conn_guid=",
+                        "https://tower.im/teams/mockteam0001/members/",
+                        "https://tower.im/members/mockmember0001/weekly_reports/2017-18/edit?\
+                         conn_guid=mockconnguid0001"]);
+
+        // and with an outdated one
+        let mut client = stale_client(&transport);
+        client.conn_guid = "staleconnguid".to_owned();
+        let answers = vec!["<p>a</p>".to_owned()];
+        client.submit_weekly_report(week(), &fields[..1], &answers).await.unwrap();
+
+        let requests = transport.requests();
+        let retry = &requests[requests.len() - 1];
+        assert_eq!(retry.url, format!("https://tower.im{}", REPORT_URL));
+        assert!(retry.body.as_ref().unwrap().starts_with("conn_guid=mockconnguid0001&data="));
+    }
+
+    #[tokio::test]
+    async fn rejected_again_after_reload() {
+        let transport = fixtures()
+            .route_response(Method::GET,
+                            "/members/stalemember/calendar_events/",
+                            sign_in_page())
+            .route_response(Method::GET,
+                            "/members/mockmember0001/calendar_events/",
+                            sign_in_page());
+        let transport = Rc::new(transport);
+        let mut client = stale_client(&transport);
+
+        match client.calendar_events().await {
+            Err(TowerError::SessionExpired) => {}
+            r => panic!("{:?}", r),
+        }
+
+        // retried only once
+        let urls: Vec<String> = transport.requests().into_iter().map(|x| x.url).collect();
+        assert_eq!(urls,
+                   vec!["https://tower.im/members/stalemember/calendar_events/",
+                        "https://tower.im/teams/mockteam0001/members/",
+                        "https://tower.im/members/mockmember0001/calendar_events/"]);
+    }
+
+    #[tokio::test]
+    async fn week_number_padded() {
+        let transport = FakeTransport::new()
+            .route(Method::GET,
+                   "/members/mockmember0001/weekly_reports/2017-05/",
+                   StatusCode::OK,
+                   include_str!("mock/fixtures/weekly_reports.html"));
+        let transport = Rc::new(transport);
+
+        let report = client(&transport).weekly_report(Week::new(2017, 5).unwrap()).await.unwrap();
+        assert_eq!(report.week, "05");
+        assert_eq!(transport.requests()[0].url,
+                   "https://tower.im/members/mockmember0001/weekly_reports/2017-05/?\
+                    conn_guid=mockconnguid0001&pjax=1");
+    }
+}
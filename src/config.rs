@@ -0,0 +1,91 @@
+
+use std::collections::HashMap;
+use std::env::{home_dir, var_os};
+use std::fs::File;
+use std::io::{BufRead, BufReader};
+use std::path::PathBuf;
+
+/// User settings from `$XDG_CONFIG_HOME/tower/config`.
+///
+/// The file holds `key = value` lines, `#` starts a comment:
+///
+/// ```text
+/// # default team, name or guid
+/// team = My Team
+/// # network timeouts in seconds, and retries of failed requests
+/// connect_timeout = 10
+/// timeout = 30
+/// retries = 3
+/// ```
+#[derive(Clone, Debug, Default)]
+pub struct Config {
+    values: HashMap<String, String>,
+}
+
+impl Config {
+    pub fn path() -> Option<PathBuf> {
+        let config_dir = match var_os("XDG_CONFIG_HOME") {
+            Some(ref dir) if !dir.is_empty() => PathBuf::from(dir),
+            _ => {
+                match home_dir() {
+                    Some(home) => home.join(".config"),
+                    None => return None,
+                }
+            }
+        };
+
+        Some(config_dir.join("tower").join("config"))
+    }
+
+    /// Load config file, empty config if not exists.
+    pub fn load() -> Config {
+        let mut config = Config::default();
+
+        let file = match Config::path().map(File::open) {
+            Some(Ok(file)) => file,
+            _ => return config,
+        };
+
+        for line in BufReader::new(file).lines() {
+            let line = match line {
+                Ok(line) => line,
+                Err(_) => continue,
+            };
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+
+            let mut kv = line.splitn(2, '=');
+            if let (Some(k), Some(v)) = (kv.next(), kv.next()) {
+                config.values.insert(k.trim().to_owned(), v.trim().to_owned());
+            }
+        }
+
+        config
+    }
+
+    pub fn get<T: AsRef<str>>(&self, key: T) -> Option<&str> {
+        self.values.get(key.as_ref()).map(|x| x.as_str())
+    }
+
+    /// Default team, name or guid.
+    pub fn team(&self) -> Option<&str> {
+        self.get("team")
+    }
+
+    /// Seconds to wait for a connection.
+    pub fn connect_timeout(&self) -> Option<u64> {
+        self.get("connect_timeout").and_then(|x| x.parse().ok())
+    }
+
+    /// Seconds to wait for a response.
+    pub fn timeout(&self) -> Option<u64> {
+        self.get("timeout").and_then(|x| x.parse().ok())
+    }
+
+    /// How many times a failed request is retried.
+    pub fn retries(&self) -> Option<u32> {
+        self.get("retries").and_then(|x| x.parse().ok())
+    }
+}
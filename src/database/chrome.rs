@@ -0,0 +1,187 @@
+
+extern crate openssl;
+
+use self::openssl::hash::{hash, MessageDigest};
+use self::openssl::pkcs5::pbkdf2_hmac;
+use self::openssl::symm::{decrypt, Cipher};
+
+// chrome on linux derives the cookie key with PBKDF2-HMAC-SHA1,
+// fixed salt and a single iteration.
+const SALT: &[u8] = b"saltysalt";
+const ITERATIONS: usize = 1;
+const KEY_LENGTH: usize = 16;
+const IV: [u8; 16] = [b' '; 16];
+
+// password used when no keyring is available (v10 values)
+const FIXED_PASSWORD: &str = "peanuts";
+
+fn derive_key(password: &[u8]) -> Option<Vec<u8>> {
+    let mut key = vec![0u8; KEY_LENGTH];
+    match pbkdf2_hmac(password, SALT, ITERATIONS, MessageDigest::sha1(), &mut key) {
+        Ok(_) => Some(key),
+        Err(_) => None,
+    }
+}
+
+fn decrypt_with(password: &[u8], data: &[u8]) -> Option<Vec<u8>> {
+    let key = derive_key(password)?;
+
+    decrypt(Cipher::aes_128_cbc(), &key, Some(&IV), data).ok()
+}
+
+// newer chrome (cookie db version >= 24) prepends sha256(host_key) to the plaintext
+fn strip_host_digest(host: &str, plain: Vec<u8>) -> Vec<u8> {
+    if plain.len() < 32 {
+        return plain;
+    }
+
+    match hash(MessageDigest::sha256(), host.as_bytes()) {
+        Ok(ref digest) if digest[..] == plain[..32] => plain[32..].to_vec(),
+        _ => plain,
+    }
+}
+
+/// Decrypt a chrome `encrypted_value` blob.
+///
+/// `v10` values are encrypted with the fixed "peanuts" password, `v11` values
+/// with a password stored in the desktop keyring, which must be supplied by the
+/// user through `user_key`.
+pub fn decrypt_value(host: &str, encrypted: &[u8], user_key: Option<&str>) -> Option<String> {
+    if encrypted.len() < 3 {
+        return None;
+    }
+
+    let (version, data) = encrypted.split_at(3);
+
+    let mut passwords: Vec<&[u8]> = Vec::new();
+    match version {
+        b"v10" => {
+            passwords.push(FIXED_PASSWORD.as_bytes());
+            if let Some(key) = user_key {
+                passwords.push(key.as_bytes());
+            }
+        }
+        b"v11" => {
+            if let Some(key) = user_key {
+                passwords.push(key.as_bytes());
+            }
+            // chrome falls back to these when the keyring is unavailable
+            passwords.push(FIXED_PASSWORD.as_bytes());
+            passwords.push(b"");
+        }
+        _ => {
+            warn!("unknown chrome cookie version: {:?}", version);
+            return None;
+        }
+    }
+
+    for password in passwords {
+        if let Some(plain) = decrypt_with(password, data) {
+            if let Ok(value) = String::from_utf8(strip_host_digest(host, plain)) {
+                return Some(value);
+            }
+        }
+    }
+
+    None
+}
+
+#[cfg(test)]
+mod tests {
+    use std::env::temp_dir;
+    use std::fs::{create_dir_all, remove_dir_all};
+    use std::path::{Path, PathBuf};
+    use std::process;
+
+    use rusqlite::types::ToSql;
+    use rusqlite::Connection;
+
+    use super::openssl::symm::{encrypt, Cipher};
+    use super::*;
+    use crate::database::{CookieSource, SqliteCookie, SqliteType};
+
+    const HOST: &str = ".tower.im";
+
+    fn encrypt_value(version: &str, password: &str, plain: &[u8]) -> Vec<u8> {
+        let key = derive_key(password.as_bytes()).unwrap();
+        let mut value = version.as_bytes().to_vec();
+        value.extend(encrypt(Cipher::aes_128_cbc(), &key, Some(&IV), plain).unwrap());
+        value
+    }
+
+    fn with_host_digest(plain: &str) -> Vec<u8> {
+        let mut value = hash(MessageDigest::sha256(), HOST.as_bytes()).unwrap().to_vec();
+        value.extend(plain.as_bytes());
+        value
+    }
+
+    // a `Cookies` database like chrome's, kept open so rows stay in the `-wal`
+    fn fixture(name: &str, rows: &[(&str, Vec<u8>)]) -> (PathBuf, Connection) {
+        let dir = temp_dir().join(format!("tower-chrome-{}-{}", process::id(), name));
+        create_dir_all(&dir).unwrap();
+        let path = dir.join("Cookies");
+
+        let conn = Connection::open(&path).unwrap();
+        conn.execute_batch("PRAGMA journal_mode = WAL;
+                            CREATE TABLE cookies (host_key TEXT, name TEXT, value TEXT,
+                                                  encrypted_value BLOB);")
+            .unwrap();
+        for &(name, ref encrypted) in rows {
+            conn.execute("INSERT INTO cookies VALUES (?, ?, '', ?)",
+                         &[&HOST as &dyn ToSql, &name, encrypted])
+                .unwrap();
+        }
+
+        (path, conn)
+    }
+
+    fn read(path: &Path, key: Option<&str>) -> SqliteCookie {
+        let mut source = SqliteCookie::new(path.to_string_lossy(), SqliteType::Chrome);
+        if let Some(key) = key {
+            source.set_chrome_key(key);
+        }
+        source.read_data().unwrap();
+        source
+    }
+
+    #[test]
+    fn v10_from_wal_database() {
+        let rows = [("remember_team_guid", encrypt_value("v10", "peanuts", b"team0001")),
+                    ("remember_token", encrypt_value("v10", "peanuts", b"token0001"))];
+        let (path, conn) = fixture("v10", &rows);
+        assert!(path.with_file_name("Cookies-wal").exists());
+
+        let source = read(&path, None);
+        assert_eq!(source.team_id(), "team0001");
+        assert_eq!(source.token(), "token0001");
+
+        drop(conn);
+        remove_dir_all(path.parent().unwrap()).unwrap();
+    }
+
+    #[test]
+    fn v11_with_user_key_and_host_digest() {
+        let token = with_host_digest("token0002");
+        let rows = [("remember_team_guid", encrypt_value("v10", "peanuts", b"team0002")),
+                    ("remember_token", encrypt_value("v11", "keyring-secret", &token))];
+        let (path, conn) = fixture("v11", &rows);
+
+        let source = read(&path, Some("keyring-secret"));
+        assert_eq!(source.team_id(), "team0002");
+        assert_eq!(source.token(), "token0002");
+
+        drop(conn);
+        remove_dir_all(path.parent().unwrap()).unwrap();
+    }
+
+    #[test]
+    fn decrypt_values() {
+        let v10 = encrypt_value("v10", "peanuts", &with_host_digest("abc"));
+        assert_eq!(decrypt_value(HOST, &v10, None), Some("abc".to_owned()));
+
+        let v11 = encrypt_value("v11", "keyring-secret", b"abc");
+        assert_eq!(decrypt_value(HOST, &v11, Some("keyring-secret")), Some("abc".to_owned()));
+        assert_eq!(decrypt_value(HOST, &v11, Some("wrong")), None);
+        assert_eq!(decrypt_value(HOST, b"v12xxxx", None), None);
+    }
+}
@@ -1,18 +1,24 @@
 
-
 extern crate rusqlite;
 
 use self::rusqlite::Connection;
-use self::rusqlite::SQLITE_OPEN_READ_ONLY;
+use self::rusqlite::{SQLITE_OPEN_READ_ONLY, SQLITE_OPEN_URI};
 
+use std::path::Path;
 use std::result::Result;
 
+use super::chrome;
+use super::error::CookieError;
+use super::source::CookieSource;
+use super::snapshot::Snapshot;
+
 pub struct SqliteCookie {
     pub path: String,
     db_type: SqliteType,
 
     team_id: String,
     token: String,
+    chrome_key: Option<String>,
 }
 
 #[derive(Clone, Debug)]
@@ -21,46 +27,113 @@ pub enum SqliteType {
     Chrome,
 }
 
+// keep the snapshot alive as long as the connection, fields drop in order
+struct CookieDb {
+    connection: Connection,
+    _snapshot: Option<Snapshot>,
+}
+
 impl SqliteCookie {
     pub fn new<T: AsRef<str>>(p: T, db_type: SqliteType) -> SqliteCookie {
         SqliteCookie {
             path: p.as_ref().to_owned(),
-            db_type: db_type,
+            db_type,
             team_id: String::new(),
             token: String::new(),
+            chrome_key: None,
         }
     }
 
-    pub fn read_data(&mut self) -> Result<(), ()> {
-        match self.db_type.clone() {
-            SqliteType::Firefox => self.read_firefox(),
-            SqliteType::Chrome => self.read_chrome(),
+    /// Keyring password used to decrypt chrome `v11` cookies.
+    pub fn set_chrome_key<T: AsRef<str>>(&mut self, key: T) {
+        self.chrome_key = Some(key.as_ref().to_owned());
+    }
+
+    // the browser may hold the database locked while running, read from a
+    // snapshot and fall back to sqlite's immutable mode if copying failed.
+    fn open(&self) -> Result<CookieDb, CookieError> {
+        match Snapshot::new(&self.path) {
+            Ok(snapshot) => {
+                debug!("read cookie snapshot: {}", snapshot.path.display());
+                Ok(CookieDb {
+                    connection: Connection::open(&snapshot.path)?,
+                    _snapshot: Some(snapshot),
+                })
+            }
+            Err(e) => {
+                warn!("snapshot {} failed: {}, open as immutable", self.path, e);
+
+                if !Path::new(&self.path).exists() {
+                    return Err(CookieError::Io(e));
+                }
+
+                let uri = format!("file:{}?immutable=1", self.path);
+                let connection_flag = SQLITE_OPEN_READ_ONLY | SQLITE_OPEN_URI;
+                Ok(CookieDb {
+                    connection: Connection::open_with_flags(uri, connection_flag)?,
+                    _snapshot: None,
+                })
+            }
         }
     }
 
-    fn read_chrome(&mut self) -> Result<(), ()> {
-        println!("{}", self.path);
+    fn read_chrome(&mut self) -> Result<(), CookieError> {
+        info!("read cookie from chrome");
+
+        let db = self.open()?;
+        let mut stmt =
+            db.connection.prepare("select host_key, name, value, encrypted_value from cookies \
+                                   where host_key = 'tower.im' or host_key = '.tower.im'")?;
+        let mut rows = stmt.query(&[])?;
+
+        while let Some(res) = rows.next() {
+            let row = res?;
+
+            let host: String = row.get_checked(0)?;
+            let name: String = row.get_checked(1)?;
+            let value: String = row.get_checked(2)?;
+            let encrypted: Vec<u8> = row.get_checked(3)?;
+
+            let value = if !value.is_empty() {
+                value
+            } else {
+                let key = self.chrome_key.as_deref();
+                match chrome::decrypt_value(&host, &encrypted, key) {
+                    Some(v) => v,
+                    None if name == "remember_token" => return Err(CookieError::Decrypt(name)),
+                    None => {
+                        warn!("decrypt cookie {} failed", name);
+                        continue;
+                    }
+                }
+            };
+            info!("got cookie: {}", name);
+
+            match name.as_ref() {
+                "remember_team_guid" => self.team_id = value,
+                "remember_token" => self.token = value,
+                _ => {}
+            }
+        }
+
         Ok(())
     }
 
-    fn read_firefox(&mut self) -> Result<(), ()> {
+    fn read_firefox(&mut self) -> Result<(), CookieError> {
         info!("read cookie from firefox");
 
-        let connection_flag = SQLITE_OPEN_READ_ONLY;
-        let connection = Connection::open_with_flags(self.path.clone(), connection_flag).unwrap();
-
+        let db = self.open()?;
         let mut stmt =
-            connection.prepare("select baseDomain, name, value from moz_cookies where baseDomain = \
-                          'tower.im' or baseDomain = '.tower.im'")
-                .unwrap();
-        let mut rows = stmt.query(&[]).unwrap();
+            db.connection.prepare("select baseDomain, name, value from moz_cookies where \
+                                   baseDomain = 'tower.im' or baseDomain = '.tower.im'")?;
+        let mut rows = stmt.query(&[])?;
 
         while let Some(res) = rows.next() {
-            let row = res.unwrap();
+            let row = res?;
 
-            let name: String = row.get(1);
-            let value: String = row.get(2);
-            info!("got cookie: name = {}, value = {}", name, value);
+            let name: String = row.get_checked(1)?;
+            let value: String = row.get_checked(2)?;
+            info!("got cookie: {}", name);
 
             match name.as_ref() {
                 "remember_team_guid" => self.team_id = value,
@@ -71,12 +144,27 @@ impl SqliteCookie {
 
         Ok(())
     }
+}
+
+impl CookieSource for SqliteCookie {
+    fn read_data(&mut self) -> Result<(), CookieError> {
+        match self.db_type.clone() {
+            SqliteType::Firefox => self.read_firefox()?,
+            SqliteType::Chrome => self.read_chrome()?,
+        }
+
+        if self.token.is_empty() {
+            return Err(CookieError::TokenNotFound);
+        }
+
+        Ok(())
+    }
 
-    pub fn team_id(&self) -> &String {
+    fn team_id(&self) -> &String {
         &self.team_id
     }
 
-    pub fn token(&self) -> &String {
+    fn token(&self) -> &String {
         &self.token
     }
 }
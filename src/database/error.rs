@@ -0,0 +1,51 @@
+
+extern crate rusqlite;
+
+use std::error::Error;
+use std::fmt;
+use std::io;
+
+#[derive(Debug)]
+pub enum CookieError {
+    Io(io::Error),
+    Sqlite(rusqlite::Error),
+    Decrypt(String),
+    Format(String),
+    Keyring(String),
+    TokenNotFound,
+}
+
+impl fmt::Display for CookieError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            CookieError::Io(ref e) => write!(f, "read cookie database failed: {}", e),
+            CookieError::Sqlite(ref e) => write!(f, "query cookie database failed: {}", e),
+            CookieError::Decrypt(ref name) => write!(f, "decrypt cookie {} failed", name),
+            CookieError::Format(ref e) => write!(f, "invalid cookie file: {}", e),
+            CookieError::Keyring(ref e) => write!(f, "access keyring failed: {}", e),
+            CookieError::TokenNotFound => write!(f, "no tower.im remember_token found"),
+        }
+    }
+}
+
+impl Error for CookieError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match *self {
+            CookieError::Io(ref e) => Some(e),
+            CookieError::Sqlite(ref e) => Some(e),
+            _ => None,
+        }
+    }
+}
+
+impl From<io::Error> for CookieError {
+    fn from(e: io::Error) -> CookieError {
+        CookieError::Io(e)
+    }
+}
+
+impl From<rusqlite::Error> for CookieError {
+    fn from(e: rusqlite::Error) -> CookieError {
+        CookieError::Sqlite(e)
+    }
+}
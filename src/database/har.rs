@@ -0,0 +1,170 @@
+
+use std::fs::File;
+use std::io::Read;
+
+use serde_json::{self, Value};
+
+use url::Url;
+
+use super::error::CookieError;
+use super::source::{is_tower_domain, CookieSource, TowerCookies};
+
+/// Cookies recorded in a HTTP Archive (HAR) file exported from browser devtools.
+///
+/// Cookies sent to and set by tower.im are collected in the recorded order.
+pub struct HarCookie {
+    pub path: String,
+    cookies: TowerCookies,
+}
+
+impl HarCookie {
+    pub fn new<T: AsRef<str>>(p: T) -> HarCookie {
+        HarCookie {
+            path: p.as_ref().to_owned(),
+            cookies: TowerCookies::new(),
+        }
+    }
+
+    fn read_cookies(&mut self, message: &Value) {
+        let cookies = match message.get("cookies").and_then(|x| x.as_array()) {
+            Some(cookies) => cookies,
+            None => return,
+        };
+
+        for cookie in cookies {
+            let name = cookie.get("name").and_then(|x| x.as_str());
+            let value = cookie.get("value").and_then(|x| x.as_str());
+
+            if let (Some(name), Some(value)) = (name, value) {
+                self.cookies.add(name, value);
+            }
+        }
+    }
+}
+
+impl CookieSource for HarCookie {
+    fn read_data(&mut self) -> Result<(), CookieError> {
+        info!("read cookie from har file");
+
+        let mut content = String::new();
+        File::open(&self.path)?.read_to_string(&mut content)?;
+
+        let har: Value = serde_json::from_str(&content)
+            .map_err(|e| CookieError::Format(e.to_string()))?;
+        let entries = match har.pointer("/log/entries").and_then(|x| x.as_array()) {
+            Some(entries) => entries,
+            None => return Err(CookieError::Format("no log.entries in har file".to_owned())),
+        };
+
+        for entry in entries {
+            let url = entry.pointer("/request/url")
+                .and_then(|x| x.as_str())
+                .and_then(|x| Url::parse(x).ok());
+            let host = url.as_ref().and_then(|x| x.host_str().map(|x| x.to_owned()));
+            if !host.is_some_and(is_tower_domain) {
+                continue;
+            }
+
+            if let Some(request) = entry.get("request") {
+                self.read_cookies(request);
+            }
+            if let Some(response) = entry.get("response") {
+                self.read_cookies(response);
+            }
+        }
+
+        self.cookies.check()
+    }
+
+    fn team_id(&self) -> &String {
+        &self.cookies.team_id
+    }
+
+    fn token(&self) -> &String {
+        &self.cookies.token
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::env::temp_dir;
+    use std::fs::{remove_file, File};
+    use std::io::Write;
+    use std::process;
+
+    use super::HarCookie;
+    use crate::database::{CookieError, CookieSource};
+
+    fn read(name: &str, content: &str) -> (Result<(), CookieError>, HarCookie) {
+        let path = temp_dir().join(format!("tower-har-{}-{}.har", process::id(), name));
+        File::create(&path).unwrap().write_all(content.as_bytes()).unwrap();
+
+        let mut cookies = HarCookie::new(path.to_string_lossy());
+        let result = cookies.read_data();
+        remove_file(&path).unwrap();
+
+        (result, cookies)
+    }
+
+    fn cookie(name: &str, value: &str) -> String {
+        format!(r#"{{"name": "{}", "value": "{}"}}"#, name, value)
+    }
+
+    fn entry(url: &str, request: &[String], response: &[String]) -> String {
+        format!(r#"{{"request": {{"url": "{}", "cookies": [{}]}},
+                    "response": {{"status": 200, "cookies": [{}]}}}}"#,
+                url,
+                request.join(","),
+                response.join(","))
+    }
+
+    fn har(entries: &[String]) -> String {
+        format!(r#"{{"log": {{"version": "1.2", "entries": [{}]}}}}"#, entries.join(","))
+    }
+
+    #[test]
+    fn request_cookies() {
+        let content = har(&[entry("https://tower.im/teams/team1/",
+                                  &[cookie("remember_token", "tok1"),
+                                    cookie("remember_team_guid", "team1")],
+                                  &[])]);
+        let (result, cookies) = read("request", &content);
+
+        result.unwrap();
+        assert_eq!(cookies.token(), "tok1");
+        assert_eq!(cookies.team_id(), "team1");
+    }
+
+    #[test]
+    fn response_cookies_win() {
+        // signed in while recording, the response sets a new token
+        let content = har(&[entry("https://tower.im/users/sign_in",
+                                  &[cookie("remember_token", "old")],
+                                  &[cookie("remember_token", "new"),
+                                    cookie("remember_team_guid", "team1")]),
+                            entry("https://example.com/",
+                                  &[cookie("remember_token", "other")],
+                                  &[])]);
+        let (result, cookies) = read("response", &content);
+
+        result.unwrap();
+        assert_eq!(cookies.token(), "new");
+        assert_eq!(cookies.team_id(), "team1");
+    }
+
+    #[test]
+    fn other_hosts() {
+        let content = har(&[entry("https://example.com/",
+                                  &[cookie("remember_token", "tok1")],
+                                  &[])]);
+        match read("other", &content).0 {
+            Err(CookieError::TokenNotFound) => {}
+            r => panic!("{:?}", r),
+        }
+
+        match read("broken", r#"{"log": {}}"#).0 {
+            Err(CookieError::Format(_)) => {}
+            r => panic!("{:?}", r),
+        }
+    }
+}
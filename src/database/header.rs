@@ -0,0 +1,96 @@
+
+use std::env::var;
+
+use super::error::CookieError;
+use super::source::{CookieSource, TowerCookies};
+
+/// Environment variable holding a raw `Cookie:` header.
+pub const COOKIE_ENV: &str = "TOWER_COOKIE";
+
+/// Cookies from a raw `Cookie:` header string, like
+/// `remember_token=xxx; remember_team_guid=yyy`.
+pub struct HeaderCookie {
+    header: String,
+    cookies: TowerCookies,
+}
+
+impl HeaderCookie {
+    pub fn new<T: AsRef<str>>(header: T) -> HeaderCookie {
+        HeaderCookie {
+            header: header.as_ref().to_owned(),
+            cookies: TowerCookies::new(),
+        }
+    }
+
+    /// Read header from `TOWER_COOKIE`, `None` if not set.
+    pub fn from_env() -> Option<HeaderCookie> {
+        match var(COOKIE_ENV) {
+            Ok(ref header) if !header.trim().is_empty() => Some(HeaderCookie::new(header)),
+            _ => None,
+        }
+    }
+}
+
+impl CookieSource for HeaderCookie {
+    fn read_data(&mut self) -> Result<(), CookieError> {
+        info!("read cookie from {}", COOKIE_ENV);
+
+        let header = self.header.trim();
+        // accept a whole header line copied from devtools
+        let header = if header.to_lowercase().starts_with("cookie:") {
+            &header["cookie:".len()..]
+        } else {
+            header
+        };
+
+        for pair in header.split(';') {
+            let mut kv = pair.trim().splitn(2, '=');
+            if let (Some(name), Some(value)) = (kv.next(), kv.next()) {
+                self.cookies.add(name.trim(), value.trim());
+            }
+        }
+
+        self.cookies.check()
+    }
+
+    fn team_id(&self) -> &String {
+        &self.cookies.team_id
+    }
+
+    fn token(&self) -> &String {
+        &self.cookies.token
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::HeaderCookie;
+    use crate::database::{CookieError, CookieSource};
+
+    #[test]
+    fn cookie_prefix() {
+        for header in &["Cookie: remember_token=tok1; remember_team_guid=team1",
+                        "cookie:remember_team_guid=team1;remember_token=tok1",
+                        "  remember_token = tok1 ; _tower2_session=x; remember_team_guid=team1  "] {
+            let mut cookies = HeaderCookie::new(header);
+            cookies.read_data().unwrap();
+            assert_eq!(cookies.token(), "tok1");
+            assert_eq!(cookies.team_id(), "team1");
+        }
+    }
+
+    #[test]
+    fn value_with_equal_sign() {
+        let mut cookies = HeaderCookie::new("remember_token=dG9rMQ==");
+        cookies.read_data().unwrap();
+        assert_eq!(cookies.token(), "dG9rMQ==");
+    }
+
+    #[test]
+    fn no_token() {
+        match HeaderCookie::new("Cookie: remember_team_guid=team1").read_data() {
+            Err(CookieError::TokenNotFound) => {}
+            r => panic!("{:?}", r),
+        }
+    }
+}
@@ -0,0 +1,109 @@
+
+extern crate secret_service;
+
+use self::secret_service::{EncryptionType, SecretService};
+
+use super::error::CookieError;
+use super::source::{CookieSource, TowerCookies};
+
+const SERVICE: &'static str = "tower.im";
+const NAMES: [&'static str; 2] = ["remember_team_guid", "remember_token"];
+
+/// Cookies kept in the desktop keyring through the freedesktop Secret Service API.
+///
+/// Every cookie is stored as a separate item with attributes
+/// `service = tower.im` and `name = <cookie name>`.
+pub struct KeyringCookie {
+    cookies: TowerCookies,
+}
+
+impl KeyringCookie {
+    pub fn new() -> KeyringCookie {
+        KeyringCookie { cookies: TowerCookies::new() }
+    }
+
+    /// Save cookies into the default collection, replacing the old ones.
+    pub fn store<T: AsRef<str>>(team_id: T, token: T) -> Result<(), CookieError> {
+        let ss = SecretService::new(EncryptionType::Dh).map_err(keyring_error)?;
+        let collection = ss.get_default_collection().map_err(keyring_error)?;
+        if collection.is_locked().map_err(keyring_error)? {
+            collection.unlock().map_err(keyring_error)?;
+        }
+
+        let values = [team_id.as_ref(), token.as_ref()];
+        for (&name, value) in NAMES.iter().zip(values.iter()) {
+            collection.create_item(&format!("tower.im {}", name),
+                             vec![("service", SERVICE), ("name", name)],
+                             value.as_bytes(),
+                             true,
+                             "text/plain")
+                .map_err(keyring_error)?;
+        }
+
+        info!("saved cookies into keyring");
+        Ok(())
+    }
+}
+
+impl CookieSource for KeyringCookie {
+    fn read_data(&mut self) -> Result<(), CookieError> {
+        info!("read cookie from keyring");
+
+        let ss = SecretService::new(EncryptionType::Dh).map_err(keyring_error)?;
+
+        for &name in NAMES.iter() {
+            let items = ss.search_items(vec![("service", SERVICE), ("name", name)])
+                .map_err(keyring_error)?;
+            let item = match items.first() {
+                Some(item) => item,
+                None => continue,
+            };
+
+            if item.is_locked().map_err(keyring_error)? {
+                item.unlock().map_err(keyring_error)?;
+            }
+
+            let secret = item.get_secret().map_err(keyring_error)?;
+            let value = String::from_utf8(secret)
+                .map_err(|_| CookieError::Keyring(format!("invalid secret of {}", name)))?;
+            self.cookies.add(name, value);
+        }
+
+        self.cookies.check()
+    }
+
+    fn team_id(&self) -> &String {
+        &self.cookies.team_id
+    }
+
+    fn token(&self) -> &String {
+        &self.cookies.token
+    }
+}
+
+fn keyring_error<E: ToString>(e: E) -> CookieError {
+    CookieError::Keyring(e.to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::env::var_os;
+
+    use crate::database::{CookieSource, KeyringCookie};
+
+    // writes to whatever Secret Service is on the session bus, so it only runs
+    // against the throwaway one started by `tests/keyring.sh`
+    #[test]
+    #[ignore]
+    fn store_and_read() {
+        assert!(var_os("TOWER_KEYRING_STAND_IN").is_some(),
+                "run tests/keyring.sh, not on the desktop keyring");
+
+        KeyringCookie::store("keyringteam0001", "keyring-test-token").unwrap();
+        let mut cookies = KeyringCookie::new();
+        cookies.read_data().unwrap();
+
+        assert_eq!(cookies.team_id(), "keyringteam0001");
+        assert_eq!(cookies.token(), "keyring-test-token");
+    }
+}
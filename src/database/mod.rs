@@ -4,4 +4,23 @@ extern crate rusqlite;
 
 pub use self::database::SqliteCookie;
 pub use self::database::SqliteType;
+pub use self::error::CookieError;
+pub use self::source::CookieSource;
+pub use self::netscape::NetscapeCookie;
+pub use self::har::HarCookie;
+pub use self::header::HeaderCookie;
+#[cfg(feature = "keyring")]
+pub use self::keyring::KeyringCookie;
+pub use self::profile::{Browser, Profile};
+#[allow(clippy::module_inception)]
 pub mod database;
+pub mod profile;
+pub mod error;
+pub mod source;
+pub mod netscape;
+pub mod har;
+pub mod header;
+#[cfg(feature = "keyring")]
+pub mod keyring;
+mod chrome;
+mod snapshot;
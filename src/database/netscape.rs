@@ -0,0 +1,115 @@
+
+use std::fs::File;
+use std::io::{BufRead, BufReader};
+
+use super::error::CookieError;
+use super::source::{is_tower_domain, CookieSource, TowerCookies};
+
+/// Cookies exported in the Netscape `cookies.txt` format, one cookie per line:
+///
+/// `domain  include_subdomains  path  secure  expires  name  value`
+pub struct NetscapeCookie {
+    pub path: String,
+    cookies: TowerCookies,
+}
+
+impl NetscapeCookie {
+    pub fn new<T: AsRef<str>>(p: T) -> NetscapeCookie {
+        NetscapeCookie {
+            path: p.as_ref().to_owned(),
+            cookies: TowerCookies::new(),
+        }
+    }
+}
+
+impl CookieSource for NetscapeCookie {
+    fn read_data(&mut self) -> Result<(), CookieError> {
+        info!("read cookie from cookies.txt");
+
+        let file = File::open(&self.path)?;
+        for line in BufReader::new(file).lines() {
+            let line = line?;
+
+            // http only cookies are exported with a special prefix
+            let line = match line.strip_prefix("#HttpOnly_") {
+                Some(line) => line,
+                None if line.starts_with('#') => continue,
+                None => &line[..],
+            };
+
+            let fields: Vec<&str> = line.trim_end_matches(['\r', '\n'])
+                .split('\t')
+                .collect();
+            if fields.len() < 7 || !is_tower_domain(fields[0]) {
+                continue;
+            }
+
+            self.cookies.add(fields[5], fields[6]);
+        }
+
+        self.cookies.check()
+    }
+
+    fn team_id(&self) -> &String {
+        &self.cookies.team_id
+    }
+
+    fn token(&self) -> &String {
+        &self.cookies.token
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::env::temp_dir;
+    use std::fs::{remove_file, File};
+    use std::io::Write;
+    use std::process;
+
+    use super::NetscapeCookie;
+    use crate::database::{CookieError, CookieSource};
+
+    fn read(name: &str, content: &str) -> (Result<(), CookieError>, NetscapeCookie) {
+        let path = temp_dir().join(format!("tower-netscape-{}-{}.txt", process::id(), name));
+        File::create(&path).unwrap().write_all(content.as_bytes()).unwrap();
+
+        let mut cookies = NetscapeCookie::new(path.to_string_lossy());
+        let result = cookies.read_data();
+        remove_file(&path).unwrap();
+
+        (result, cookies)
+    }
+
+    #[test]
+    fn http_only_prefix() {
+        let (result, cookies) = read("http-only",
+                                     "# Netscape HTTP Cookie File\n\
+                                      .tower.im\tTRUE\t/\tFALSE\t0\tremember_team_guid\tteam1\n\
+                                      #HttpOnly_.tower.im\tTRUE\t/\tTRUE\t0\tremember_token\ttok1\n\
+                                      #.tower.im\tTRUE\t/\tTRUE\t0\tremember_token\tcommented\n");
+        result.unwrap();
+        assert_eq!(cookies.team_id(), "team1");
+        assert_eq!(cookies.token(), "tok1");
+    }
+
+    #[test]
+    fn crlf_lines() {
+        let (result, cookies) = read("crlf",
+                                     "tower.im\tFALSE\t/\tTRUE\t0\tremember_token\ttok1\r\n\
+                                      .tower.im\tTRUE\t/\tFALSE\t0\tremember_team_guid\tteam1\r\n");
+        result.unwrap();
+        assert_eq!(cookies.token(), "tok1");
+        assert_eq!(cookies.team_id(), "team1");
+    }
+
+    #[test]
+    fn other_domains() {
+        let (result, _) = read("other",
+                               ".example.com\tTRUE\t/\tFALSE\t0\tremember_token\ttok1\n\
+                                .tower.im\tTRUE\t/\tFALSE\t0\tremember_token\n");
+        match result {
+            Err(CookieError::TokenNotFound) => {}
+            r => panic!("{:?}", r),
+        }
+    }
+}
@@ -0,0 +1,417 @@
+
+use std::env::home_dir;
+use std::fs::{read_dir, File};
+use std::io::{BufRead, BufReader, Read};
+use std::path::{Path, PathBuf};
+
+use serde_json::{self, Value};
+
+use super::CookieSource;
+use super::SqliteCookie;
+use super::SqliteType;
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum Browser {
+    Firefox,
+    Chrome,
+    Chromium,
+    Brave,
+    Vivaldi,
+    Edge,
+}
+
+impl Browser {
+    pub fn name(&self) -> &'static str {
+        match *self {
+            Browser::Firefox => "firefox",
+            Browser::Chrome => "chrome",
+            Browser::Chromium => "chromium",
+            Browser::Brave => "brave",
+            Browser::Vivaldi => "vivaldi",
+            Browser::Edge => "edge",
+        }
+    }
+
+    pub fn from_name<T: AsRef<str>>(name: T) -> Option<Browser> {
+        match name.as_ref().to_lowercase().as_str() {
+            "firefox" => Some(Browser::Firefox),
+            "chrome" | "google-chrome" => Some(Browser::Chrome),
+            "chromium" => Some(Browser::Chromium),
+            "brave" => Some(Browser::Brave),
+            "vivaldi" => Some(Browser::Vivaldi),
+            "edge" | "microsoft-edge" => Some(Browser::Edge),
+            _ => None,
+        }
+    }
+
+    pub fn db_type(&self) -> SqliteType {
+        match *self {
+            Browser::Firefox => SqliteType::Firefox,
+            _ => SqliteType::Chrome,
+        }
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct Profile {
+    pub browser: Browser,
+    pub name: String,
+    pub dir: PathBuf,
+    pub cookie_path: PathBuf,
+    pub is_default: bool,
+}
+
+impl Profile {
+    /// Check whether this profile holds a tower.im `remember_token`.
+    pub fn has_token(&self, chrome_key: Option<&str>) -> bool {
+        let mut sc = SqliteCookie::new(self.cookie_path.to_string_lossy(),
+                                       self.browser.db_type());
+        if let Some(key) = chrome_key {
+            sc.set_chrome_key(key);
+        }
+
+        sc.read_data().is_ok()
+    }
+
+    /// Match against a `--profile` argument, either profile name or directory name.
+    pub fn matches<T: AsRef<str>>(&self, name: T) -> bool {
+        let name = name.as_ref();
+        let dir_name = self.dir.file_name().map(|x| x.to_string_lossy().into_owned());
+
+        self.name == name || dir_name.as_deref() == Some(name)
+    }
+}
+
+/// Find every firefox and chromium-family profile that has a cookie database.
+pub fn discover_profiles() -> Vec<Profile> {
+    let home = match home_dir() {
+        Some(home) => home,
+        None => return vec![],
+    };
+
+    let mut profiles = vec![];
+
+    for root in firefox_roots(&home) {
+        profiles.extend(firefox_profiles(&root));
+    }
+
+    for (browser, root) in chromium_roots(&home) {
+        profiles.extend(chromium_profiles(browser, &root));
+    }
+
+    profiles
+}
+
+/// Pick the profile to read cookies from.
+///
+/// Profiles are filtered by `browser` and `name` when given, then the first
+/// default profile holding a tower.im token wins, followed by any profile
+/// holding a token. A profile asked by `name` is used even without a token, so
+/// `None` then means no such profile.
+pub fn select_profile<'a>(profiles: &'a [Profile],
+                          browser: Option<&Browser>,
+                          name: Option<&str>,
+                          chrome_key: Option<&str>)
+                          -> Option<&'a Profile> {
+    let candidates: Vec<&Profile> = profiles.iter()
+        .filter(|p| browser.is_none_or(|b| &p.browser == b))
+        .filter(|p| name.is_none_or(|n| p.matches(n)))
+        .collect();
+
+    // user asked a specific profile, use it even if no token found
+    if name.is_some() && candidates.len() == 1 {
+        return candidates.into_iter().next();
+    }
+
+    let with_token: Vec<&Profile> =
+        candidates.iter().cloned().filter(|p| p.has_token(chrome_key)).collect();
+
+    let found = with_token.iter()
+        .find(|p| p.is_default)
+        .or(with_token.first())
+        .copied();
+
+    match name {
+        Some(_) => found.or(candidates.first().cloned()),
+        None => found,
+    }
+}
+
+fn firefox_roots(home: &Path) -> Vec<PathBuf> {
+    vec![home.join(".mozilla/firefox"),
+         home.join("snap/firefox/common/.mozilla/firefox"),
+         home.join(".var/app/org.mozilla.firefox/.mozilla/firefox")]
+}
+
+fn chromium_roots(home: &Path) -> Vec<(Browser, PathBuf)> {
+    vec![(Browser::Chrome, home.join(".config/google-chrome")),
+         (Browser::Chrome, home.join(".var/app/com.google.Chrome/config/google-chrome")),
+         (Browser::Chromium, home.join(".config/chromium")),
+         (Browser::Chromium, home.join("snap/chromium/common/chromium")),
+         (Browser::Chromium, home.join(".var/app/org.chromium.Chromium/config/chromium")),
+         (Browser::Brave, home.join(".config/BraveSoftware/Brave-Browser")),
+         (Browser::Brave, home.join("snap/brave/current/.config/BraveSoftware/Brave-Browser")),
+         (Browser::Brave,
+          home.join(".var/app/com.brave.Browser/config/BraveSoftware/Brave-Browser")),
+         (Browser::Vivaldi, home.join(".config/vivaldi")),
+         (Browser::Vivaldi, home.join(".var/app/com.vivaldi.Vivaldi/config/vivaldi")),
+         (Browser::Edge, home.join(".config/microsoft-edge")),
+         (Browser::Edge, home.join(".var/app/com.microsoft.Edge/config/microsoft-edge"))]
+}
+
+// parse profiles.ini, which looks like:
+//
+// [Install4F96D1932A9F858E]
+// Default=abcd.default-release
+//
+// [Profile0]
+// Name=default
+// IsRelative=1
+// Path=abcd.default
+// Default=1
+fn firefox_profiles(root: &Path) -> Vec<Profile> {
+    let file = match File::open(root.join("profiles.ini")) {
+        Ok(file) => file,
+        Err(_) => return vec![],
+    };
+
+    let mut sections: Vec<(String, Vec<(String, String)>)> = vec![];
+    for line in BufReader::new(file).lines() {
+        let line = match line {
+            Ok(line) => line,
+            Err(_) => continue,
+        };
+        let line = line.trim();
+
+        if line.starts_with('[') && line.ends_with(']') {
+            sections.push((line[1..line.len() - 1].to_owned(), vec![]));
+            continue;
+        }
+
+        let mut kv = line.splitn(2, '=');
+        if let (Some(k), Some(v), Some(section)) = (kv.next(), kv.next(), sections.last_mut()) {
+            section.1.push((k.trim().to_owned(), v.trim().to_owned()));
+        }
+    }
+
+    let get = |entries: &Vec<(String, String)>, key: &str| -> Option<String> {
+        entries.iter().find(|&(k, _)| k == key).map(|(_, v)| v.clone())
+    };
+
+    // profiles used by firefox installations take precedence over `Default=1`
+    let install_defaults: Vec<String> = sections.iter()
+        .filter(|&(name, _)| name.starts_with("Install"))
+        .filter_map(|(_, entries)| get(entries, "Default"))
+        .collect();
+
+    let mut profiles = vec![];
+    for (section, entries) in &sections {
+        if !section.starts_with("Profile") {
+            continue;
+        }
+
+        let path = match get(entries, "Path") {
+            Some(path) => path,
+            None => continue,
+        };
+
+        let dir = if get(entries, "IsRelative").is_none_or(|x| x == "1") {
+            root.join(&path)
+        } else {
+            PathBuf::from(&path)
+        };
+
+        let cookie_path = dir.join("cookies.sqlite");
+        if !cookie_path.exists() {
+            continue;
+        }
+
+        let is_default = if install_defaults.is_empty() {
+            get(entries, "Default").is_some_and(|x| x == "1")
+        } else {
+            install_defaults.contains(&path)
+        };
+
+        profiles.push(Profile {
+            browser: Browser::Firefox,
+            name: get(entries, "Name").unwrap_or(path),
+            dir,
+            cookie_path,
+            is_default,
+        });
+    }
+
+    profiles
+}
+
+fn chromium_profiles(browser: Browser, root: &Path) -> Vec<Profile> {
+    let entries = match read_dir(root) {
+        Ok(entries) => entries,
+        Err(_) => return vec![],
+    };
+
+    let local_state = read_local_state(root);
+    let last_used = local_state.as_ref()
+        .and_then(|x| x.pointer("/profile/last_used"))
+        .and_then(|x| x.as_str())
+        .unwrap_or("Default")
+        .to_owned();
+
+    let mut profiles = vec![];
+    for entry in entries.filter_map(|x| x.ok()) {
+        let dir = entry.path();
+        let dir_name = entry.file_name().to_string_lossy().into_owned();
+
+        // newer versions moved the cookie database into `Network`
+        let cookie_path = match ["Network/Cookies", "Cookies"]
+            .iter()
+            .map(|x| dir.join(x))
+            .find(|x| x.exists()) {
+            Some(path) => path,
+            None => continue,
+        };
+
+        let name = local_state.as_ref()
+            .and_then(|x| x.pointer("/profile/info_cache"))
+            .and_then(|x| x.get(&dir_name))
+            .and_then(|x| x.get("name"))
+            .and_then(|x| x.as_str())
+            .map(|x| x.to_owned())
+            .unwrap_or(dir_name.clone());
+
+        profiles.push(Profile {
+            browser: browser.clone(),
+            name,
+            dir,
+            cookie_path,
+            is_default: dir_name == last_used,
+        });
+    }
+
+    profiles.sort_by(|a, b| a.dir.cmp(&b.dir));
+    profiles
+}
+
+fn read_local_state(root: &Path) -> Option<Value> {
+    let mut content = String::new();
+    match File::open(root.join("Local State")) {
+        Ok(mut file) => {
+            if file.read_to_string(&mut content).is_err() {
+                return None;
+            }
+        }
+        Err(_) => return None,
+    }
+
+    serde_json::from_str(&content).ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use std::env::temp_dir;
+    use std::fs::{create_dir_all, remove_dir_all, File};
+    use std::io::Write;
+    use std::path::{Path, PathBuf};
+    use std::process;
+
+    use super::*;
+
+    fn root(name: &str) -> PathBuf {
+        let root = temp_dir().join(format!("tower-profile-{}-{}", process::id(), name));
+        let _ = remove_dir_all(&root);
+        create_dir_all(&root).unwrap();
+        root
+    }
+
+    fn write(path: &Path, content: &str) {
+        create_dir_all(path.parent().unwrap()).unwrap();
+        File::create(path).unwrap().write_all(content.as_bytes()).unwrap();
+    }
+
+    fn names(profiles: &[Profile]) -> Vec<(&str, bool)> {
+        profiles.iter().map(|x| (x.name.as_str(), x.is_default)).collect()
+    }
+
+    #[test]
+    fn firefox_profiles_ini() {
+        let root = root("firefox");
+        let absolute = root.join("elsewhere/work.profile");
+        write(&root.join("abcd.default/cookies.sqlite"), "");
+        write(&root.join("efgh.default-release/cookies.sqlite"), "");
+        write(&absolute.join("cookies.sqlite"), "");
+        write(&root.join("profiles.ini"),
+              &format!("[Profile0]\nName=default\nIsRelative=1\nPath=abcd.default\n\
+                        Default=1\n\n\
+                        [Profile1]\nName=default-release\nIsRelative=1\n\
+                        Path=efgh.default-release\n\n\
+                        [Profile2]\nName=work\nIsRelative=0\nPath={}\n\n\
+                        [Profile3]\nName=no cookies\nIsRelative=1\nPath=empty\n\n\
+                        [General]\nStartWithLastProfile=1\n",
+                       absolute.display()));
+
+        // without `Install*` sections `Default=1` wins
+        let profiles = firefox_profiles(&root);
+        assert_eq!(names(&profiles),
+                   vec![("default", true), ("default-release", false), ("work", false)]);
+        assert_eq!(profiles[1].cookie_path, root.join("efgh.default-release/cookies.sqlite"));
+        assert_eq!(profiles[2].dir, absolute);
+
+        let ini = root.join("profiles.ini");
+        let mut content = String::new();
+        File::open(&ini).unwrap().read_to_string(&mut content).unwrap();
+        write(&ini,
+              &format!("[Install4F96D1932A9F858E]\nDefault=efgh.default-release\n\n{}",
+                       content));
+
+        let profiles = firefox_profiles(&root);
+        assert_eq!(names(&profiles),
+                   vec![("default", false), ("default-release", true), ("work", false)]);
+
+        remove_dir_all(&root).unwrap();
+    }
+
+    #[test]
+    fn chromium_local_state() {
+        let root = root("chromium");
+        write(&root.join("Default/Cookies"), "");
+        write(&root.join("Profile 1/Network/Cookies"), "");
+        write(&root.join("Profile 2/Preferences"), "{}");
+        write(&root.join("Local State"),
+              r#"{"profile": {"last_used": "Profile 1",
+                              "info_cache": {"Default": {"name": "Person 1"},
+                                             "Profile 1": {"name": "工作"}}}}"#);
+
+        let profiles = chromium_profiles(Browser::Chromium, &root);
+        assert_eq!(names(&profiles), vec![("Person 1", false), ("工作", true)]);
+        assert_eq!(profiles[1].cookie_path, root.join("Profile 1/Network/Cookies"));
+        assert!(profiles[1].matches("Profile 1"));
+        assert!(profiles[1].matches("工作"));
+
+        // names are directory names without `Local State`
+        write(&root.join("Local State"), "{");
+        let profiles = chromium_profiles(Browser::Chromium, &root);
+        assert_eq!(names(&profiles), vec![("Default", true), ("Profile 1", false)]);
+
+        remove_dir_all(&root).unwrap();
+    }
+
+    #[test]
+    fn select_profile_by_name() {
+        let root = root("select");
+        write(&root.join("Default/Cookies"), "");
+        write(&root.join("Profile 1/Cookies"), "");
+        let mut profiles = chromium_profiles(Browser::Chrome, &root);
+        profiles.extend(chromium_profiles(Browser::Chromium, &root));
+
+        // no profile holds a token, but the one asked for is used
+        let found = select_profile(&profiles, Some(&Browser::Chromium), Some("Profile 1"), None);
+        assert_eq!(found.map(|x| &x.browser), Some(&Browser::Chromium));
+        let found = select_profile(&profiles, None, Some("Profile 1"), None);
+        assert_eq!(found.map(|x| x.name.as_str()), Some("Profile 1"));
+
+        assert!(select_profile(&profiles, None, Some("Profile 9"), None).is_none());
+        assert!(select_profile(&profiles, None, None, None).is_none());
+
+        remove_dir_all(&root).unwrap();
+    }
+}
@@ -0,0 +1,64 @@
+
+use std::env::temp_dir;
+use std::fs::{copy, remove_dir_all, DirBuilder};
+use std::io;
+use std::os::unix::fs::DirBuilderExt;
+use std::path::{Path, PathBuf};
+use std::process;
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+static SNAPSHOT_COUNT: AtomicUsize = AtomicUsize::new(0);
+
+/// A private copy of a sqlite database together with its `-wal`/`-shm` files.
+///
+/// Browsers keep their cookie database locked in WAL mode while running, and
+/// the freshest cookies are often still in the `-wal` file. Copying all of them
+/// lets sqlite replay the log on our copy without touching the browser's files.
+/// The copy is removed on drop.
+pub struct Snapshot {
+    dir: PathBuf,
+    pub path: PathBuf,
+}
+
+impl Snapshot {
+    pub fn new<P: AsRef<Path>>(src: P) -> io::Result<Snapshot> {
+        let src = src.as_ref();
+        let file_name = match src.file_name() {
+            Some(name) => name.to_owned(),
+            None => return Err(io::Error::new(io::ErrorKind::InvalidInput, "not a file")),
+        };
+
+        let dir = temp_dir().join(format!("tower-{}-{}",
+                                          process::id(),
+                                          SNAPSHOT_COUNT.fetch_add(1, Ordering::SeqCst)));
+        // cookies are credentials, keep the copy private
+        DirBuilder::new().mode(0o700).create(&dir)?;
+
+        let snapshot = Snapshot {
+            path: dir.join(&file_name),
+            dir,
+        };
+
+        copy(src, &snapshot.path)?;
+
+        for suffix in &["-wal", "-shm"] {
+            let mut name = file_name.clone();
+            name.push(suffix);
+
+            let extra = src.with_file_name(&name);
+            if extra.exists() {
+                copy(&extra, snapshot.dir.join(&name))?;
+            }
+        }
+
+        Ok(snapshot)
+    }
+}
+
+impl Drop for Snapshot {
+    fn drop(&mut self) {
+        if let Err(e) = remove_dir_all(&self.dir) {
+            warn!("remove snapshot {} failed: {}", self.dir.display(), e);
+        }
+    }
+}
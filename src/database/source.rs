@@ -0,0 +1,49 @@
+
+use super::error::CookieError;
+
+/// Somewhere tower.im cookies can be read from.
+pub trait CookieSource {
+    fn read_data(&mut self) -> Result<(), CookieError>;
+
+    fn team_id(&self) -> &String;
+
+    fn token(&self) -> &String;
+}
+
+/// The tower.im cookies every source is looking for.
+#[derive(Clone, Debug, Default)]
+pub struct TowerCookies {
+    pub team_id: String,
+    pub token: String,
+}
+
+impl TowerCookies {
+    pub fn new() -> TowerCookies {
+        TowerCookies::default()
+    }
+
+    /// Keep the cookie if it is one we need, later values win.
+    pub fn add<N: AsRef<str>, V: AsRef<str>>(&mut self, name: N, value: V) {
+        match name.as_ref() {
+            "remember_team_guid" => self.team_id = value.as_ref().to_owned(),
+            "remember_token" => self.token = value.as_ref().to_owned(),
+            _ => return,
+        }
+
+        info!("got cookie: {}", name.as_ref());
+    }
+
+    pub fn check(&self) -> Result<(), CookieError> {
+        if self.token.is_empty() {
+            return Err(CookieError::TokenNotFound);
+        }
+
+        Ok(())
+    }
+}
+
+/// Whether `domain` is a cookie domain of tower.im.
+pub fn is_tower_domain<T: AsRef<str>>(domain: T) -> bool {
+    let domain = domain.as_ref();
+    domain == "tower.im" || domain == ".tower.im"
+}
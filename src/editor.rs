@@ -0,0 +1,209 @@
+
+//! Fill in weekly report answers in `$VISUAL` or `$EDITOR`.
+//!
+//! All questions go into one temp file, each answer below a header line:
+//!
+//! ```text
+//! # Write your answers below each question, then save and quit.
+//! # Lines above the first question are ignored. Leave the file unchanged to abort.
+//!
+//! === What did you do this week? ===
+//! - fixed the login page
+//!
+//! === What's next? ===
+//! ```
+
+use std::env::{temp_dir, var};
+use std::fs::{remove_file, File, OpenOptions};
+use std::io::{self, Read, Write};
+use std::os::unix::fs::OpenOptionsExt;
+use std::path::Path;
+use std::process::{self, Command};
+
+use crate::error::{Result, TowerError};
+use crate::types::WeeklyField;
+
+const INSTRUCTIONS: &str = "# Write your answers below each question, then save and \
+                                    quit.\n# Lines above the first question are ignored. Leave \
+                                    the file unchanged to abort.\n";
+
+// titles are written on one line
+fn normalize(title: &str) -> String {
+    title.split_whitespace().collect::<Vec<_>>().join(" ")
+}
+
+fn header(title: &str) -> String {
+    format!("=== {} ===", normalize(title))
+}
+
+// `=== title ===` -> `title`
+fn header_title(line: &str) -> Option<&str> {
+    let line = line.trim();
+    if line.len() > 8 && line.starts_with("=== ") && line.ends_with(" ===") {
+        Some(line[4..line.len() - 4].trim())
+    } else {
+        None
+    }
+}
+
+/// File content with a section for every field, holding the answer given.
+pub fn template(fields: &[WeeklyField], answers: &[String]) -> String {
+    let mut content = INSTRUCTIONS.to_owned();
+
+    for (i, field) in fields.iter().enumerate() {
+        content.push('\n');
+        content.push_str(&header(&field.title));
+        content.push('\n');
+        if let Some(answer) = answers.get(i) {
+            let answer = answer.trim();
+            if !answer.is_empty() {
+                content.push_str(answer);
+                content.push('\n');
+            }
+        }
+    }
+
+    content
+}
+
+/// Answers of `fields` in an edited template, in field order.
+///
+/// Sections are matched by title, every field must have one.
+pub fn parse(fields: &[WeeklyField], content: &str) -> Result<Vec<String>> {
+    let mut sections: Vec<(String, Vec<&str>)> = vec![];
+
+    for line in content.lines() {
+        match header_title(line) {
+            Some(title) => sections.push((normalize(title), vec![])),
+            None => {
+                if let Some(section) = sections.last_mut() {
+                    section.1.push(line);
+                }
+            }
+        }
+    }
+
+    let mut answers = vec![];
+    for field in fields {
+        let pos = match sections.iter().position(|x| field.matches(&x.0)) {
+            Some(pos) => pos,
+            None => {
+                return Err(TowerError::Parse(format!("`{}` in edited file", header(&field.title))))
+            }
+        };
+
+        let (_, lines) = sections.remove(pos);
+        answers.push(lines.join("\n").trim().to_owned());
+    }
+
+    Ok(answers)
+}
+
+/// Open `path` in `$VISUAL`, `$EDITOR` or vi, and wait until it quits.
+pub fn open_editor(path: &Path) -> Result<()> {
+    let editor = var("VISUAL")
+        .or(var("EDITOR"))
+        .ok()
+        .filter(|x| !x.trim().is_empty())
+        .unwrap_or("vi".to_owned());
+
+    // through the shell, so editors with arguments like `code --wait` work
+    let status = Command::new("sh")
+        .arg("-c")
+        .arg(format!("{} \"$@\"", editor))
+        .arg(&editor)
+        .arg(path)
+        .status()?;
+
+    if !status.success() {
+        let msg = format!("editor `{}` exited with {}", editor, status);
+        return Err(io::Error::other(msg).into());
+    }
+
+    Ok(())
+}
+
+/// Let the user edit `answers` of `fields` in one file.
+///
+/// Fails with `TowerError::Aborted` if the file is saved unchanged.
+pub fn edit_answers(fields: &[WeeklyField], answers: &[String]) -> Result<Vec<String>> {
+    let path = temp_dir().join(format!("tower-weekly-{}.txt", process::id()));
+    let content = template(fields, answers);
+
+    // answers may be private, keep them from other users
+    let mut file = OpenOptions::new().write(true)
+        .create_new(true)
+        .mode(0o600)
+        .open(&path)?;
+    file.write_all(content.as_bytes())?;
+    drop(file);
+
+    let edited = open_editor(&path).and_then(|_| {
+        let mut edited = String::new();
+        File::open(&path)?.read_to_string(&mut edited)?;
+        Ok(edited)
+    });
+    let _ = remove_file(&path);
+    let edited = edited?;
+
+    if edited == content {
+        return Err(TowerError::Aborted);
+    }
+
+    parse(fields, &edited)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{parse, template};
+    use crate::error::TowerError;
+    use crate::types::WeeklyField;
+
+    fn fields() -> Vec<WeeklyField> {
+        ["本周完成的工作", "What's   next\nweek?", "需要的帮助"]
+            .iter()
+            .enumerate()
+            .map(|(i, title)| {
+                WeeklyField {
+                    name: "question_guid".to_owned(),
+                    value: format!("q{}", i),
+                    title: title.to_string(),
+                }
+            })
+            .collect()
+    }
+
+    #[test]
+    fn round_trip() {
+        let answers = vec!["- 登录页\n- cookie\n\n**done**".to_owned(),
+                           "  发布  \n".to_owned(),
+                           String::new()];
+        let content = template(&fields(), &answers);
+
+        assert!(content.contains("\n=== What's next week? ===\n发布\n"));
+        assert_eq!(parse(&fields(), &content).unwrap(),
+                   vec!["- 登录页\n- cookie\n\n**done**", "发布", ""]);
+    }
+
+    #[test]
+    fn titles_with_other_spacing() {
+        let content = "ignored\n\
+                       ===   需要的帮助 ===\nnone\n\
+                       === What's next  week? ===\n  plan\n\
+                       ===  本周完成的工作   ===\nwork\n";
+
+        assert_eq!(parse(&fields(), content).unwrap(), vec!["work", "plan", "none"]);
+    }
+
+    #[test]
+    fn missing_section() {
+        let content = "=== 本周完成的工作 ===\nwork\n=== 需要的帮助 ===\n";
+
+        match parse(&fields(), content) {
+            Err(TowerError::Parse(what)) => {
+                assert_eq!(what, "`=== What's next week? ===` in edited file")
+            }
+            r => panic!("{:?}", r),
+        }
+    }
+}
@@ -0,0 +1,136 @@
+
+use std::error::Error;
+use std::fmt;
+use std::io;
+
+use reqwest::StatusCode;
+
+use crate::database::CookieError;
+
+#[derive(Debug)]
+pub enum TowerError {
+    Cookie(CookieError),
+    Io(io::Error),
+    Http(reqwest::Error),
+    Status(StatusCode, String),
+    Json(serde_json::Error),
+    Parse(String),
+    /// csrf token not found in the page
+    CsrfMissing(String),
+    SessionExpired,
+    /// no cookies of tower.im found anywhere
+    NotLoggedIn,
+    LoginFailed,
+    MemberNotFound(String),
+    TeamNotFound(String),
+    /// no browser profile matches `--profile`
+    ProfileNotFound(String),
+    Rejected(String),
+    /// report file does not fit the weekly report form
+    InvalidReport(String),
+    /// user quit a prompt
+    Aborted,
+}
+
+pub type Result<T> = ::std::result::Result<T, TowerError>;
+
+impl TowerError {
+    /// Process exit code, so scripts can tell what went wrong.
+    ///
+    /// | code | error |
+    /// |------|-------|
+    /// | 1 | io, network, cookie database and other errors |
+    /// | 2 | session expired or not logged in |
+    /// | 3 | wrong email or password |
+    /// | 4 | csrf token missing |
+    /// | 5 | unexpected http status |
+    /// | 6 | page, json or report file parse failure |
+    /// | 7 | member, team or browser profile not found |
+    /// | 8 | request rejected by tower.im |
+    /// | 9 | aborted by user |
+    pub fn exit_code(&self) -> i32 {
+        match *self {
+            TowerError::Cookie(_) |
+            TowerError::Io(_) |
+            TowerError::Http(_) => 1,
+            TowerError::SessionExpired |
+            TowerError::NotLoggedIn => 2,
+            TowerError::LoginFailed => 3,
+            TowerError::CsrfMissing(_) => 4,
+            TowerError::Status(..) => 5,
+            TowerError::Json(_) |
+            TowerError::Parse(_) |
+            TowerError::InvalidReport(_) => 6,
+            TowerError::MemberNotFound(_) |
+            TowerError::TeamNotFound(_) |
+            TowerError::ProfileNotFound(_) => 7,
+            TowerError::Rejected(_) => 8,
+            TowerError::Aborted => 9,
+        }
+    }
+}
+
+impl fmt::Display for TowerError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            TowerError::Cookie(ref e) => write!(f, "{}", e),
+            TowerError::Io(ref e) => write!(f, "{}", e),
+            TowerError::Http(ref e) => write!(f, "request failed: {}", e),
+            TowerError::Status(ref status, ref url) => write!(f, "{} returns {}", url, status),
+            TowerError::Json(ref e) => write!(f, "invalid json response: {}", e),
+            TowerError::Parse(ref what) => write!(f, "find {} failed", what),
+            TowerError::CsrfMissing(ref page) => write!(f, "csrf token not found in {}", page),
+            TowerError::SessionExpired => write!(f, "session expired, please log in to tower.im"),
+            TowerError::NotLoggedIn => {
+                write!(f,
+                       "no cookies of tower.im found, please log in to tower.im in a browser \
+                        or run `tower login`")
+            }
+            TowerError::LoginFailed => write!(f, "wrong email or password"),
+            TowerError::MemberNotFound(ref name) => write!(f, "User {} not exist!", name),
+            TowerError::TeamNotFound(ref name) => write!(f, "Team {} not exist!", name),
+            TowerError::ProfileNotFound(ref name) => {
+                write!(f, "browser profile {} not found", name)
+            }
+            TowerError::Rejected(ref msg) => write!(f, "tower.im rejected the request: {}", msg),
+            TowerError::InvalidReport(ref msg) => write!(f, "invalid report file: {}", msg),
+            TowerError::Aborted => write!(f, "aborted"),
+        }
+    }
+}
+
+impl Error for TowerError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match *self {
+            TowerError::Cookie(ref e) => Some(e),
+            TowerError::Io(ref e) => Some(e),
+            TowerError::Http(ref e) => Some(e),
+            TowerError::Json(ref e) => Some(e),
+            _ => None,
+        }
+    }
+}
+
+impl From<CookieError> for TowerError {
+    fn from(e: CookieError) -> TowerError {
+        TowerError::Cookie(e)
+    }
+}
+
+impl From<io::Error> for TowerError {
+    fn from(e: io::Error) -> TowerError {
+        TowerError::Io(e)
+    }
+}
+
+impl From<reqwest::Error> for TowerError {
+    fn from(e: reqwest::Error) -> TowerError {
+        TowerError::Http(e)
+    }
+}
+
+impl From<serde_json::Error> for TowerError {
+    fn from(e: serde_json::Error) -> TowerError {
+        TowerError::Json(e)
+    }
+}
@@ -0,0 +1,44 @@
+
+use url::form_urlencoded::Serializer;
+
+/// An `application/x-www-form-urlencoded` request body.
+///
+/// ```ignore
+/// let body = Form::new().append("content", "加班登记").append("is_html", "1").finish();
+/// ```
+pub struct Form {
+    serializer: Serializer<'static, String>,
+}
+
+impl Form {
+    pub fn new() -> Form {
+        Form { serializer: Serializer::new(String::new()) }
+    }
+
+    /// Add a field, `name` and `value` are escaped.
+    pub fn append<N: AsRef<str>, V: AsRef<str>>(mut self, name: N, value: V) -> Form {
+        self.serializer.append_pair(name.as_ref(), value.as_ref());
+        self
+    }
+
+    pub fn finish(mut self) -> String {
+        self.serializer.finish()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Form;
+
+    #[test]
+    fn escapes_non_ascii() {
+        let body = Form::new().append("content", "加班登记 & more").finish();
+        assert_eq!(body, "content=%E5%8A%A0%E7%8F%AD%E7%99%BB%E8%AE%B0+%26+more");
+    }
+
+    #[test]
+    fn escapes_reserved_chars() {
+        let body = Form::new().append("a&b", "a & b=c +d\n中").append("is_html", "1").finish();
+        assert_eq!(body, "a%26b=a+%26+b%3Dc+%2Bd%0A%E4%B8%AD&is_html=1");
+    }
+}
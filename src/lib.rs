@@ -0,0 +1,41 @@
+
+#[macro_use]
+extern crate log;
+extern crate async_trait;
+#[cfg(feature = "mock")]
+extern crate hyper;
+extern crate regex;
+extern crate reqwest;
+extern crate time;
+extern crate tokio;
+extern crate serde;
+#[macro_use]
+extern crate serde_derive;
+extern crate serde_json;
+extern crate serde_yaml;
+extern crate url;
+extern crate scraper;
+
+pub mod database;
+pub mod session;
+pub mod config;
+pub mod team;
+pub mod transport;
+pub mod proxy;
+#[cfg(feature = "mock")]
+pub mod mock;
+pub mod cassette;
+pub mod parser;
+pub mod editor;
+pub mod markdown;
+pub mod report;
+pub mod week;
+mod api;
+mod client;
+mod form;
+mod error;
+mod types;
+
+pub use client::TowerClient;
+pub use error::{Result, TowerError};
+pub use types::{CalendarEvent, Member, Overview, WeeklyField, WeeklyReport, WeeklyReportSection};
@@ -2,391 +2,240 @@
 #[macro_use]
 extern crate log;
 extern crate env_logger;
-#[macro_use]
-extern crate hyper;
-extern crate hyper_native_tls;
-extern crate regex;
-extern crate time;
-extern crate rustc_serialize;
 extern crate clap;
-
-mod database;
+extern crate libc;
+extern crate tokio;
+extern crate tower;
 
 use std::io::*;
 use std::env::*;
-use std::fs::File;
-use std::path::Path;
-use std::collections::HashMap;
 use std::process::Command;
-use std::os::unix::process::CommandExt;
-
-use clap::{Arg, App};
-
-use time::*;
-
-use rustc_serialize::json::*;
-
-use database::SqliteCookie;
-use database::SqliteType;
-
-use hyper::client::*;
-use hyper::header::*;
-use hyper::status::StatusCode;
-use hyper::net::HttpsConnector;
-use hyper_native_tls::NativeTlsClient;
-
-use regex::Regex;
-
-header! { (XCSRFToken, "X-CSRF-Token") => [String] }
-header! { (POSTAccept, "Accept") => [String] }
+use std::process::exit;
+use std::time::Duration;
+use std::mem;
+use std::path::{Path, PathBuf};
+
+use clap::{Arg, App, AppSettings, ArgMatches, SubCommand};
+
+use tower::{Overview, TowerClient, TowerError, WeeklyField, WeeklyReport};
+use tower::database::{CookieSource, HarCookie, HeaderCookie, NetscapeCookie, SqliteCookie};
+#[cfg(feature = "keyring")]
+use tower::database::KeyringCookie;
+use tower::database::Browser;
+use tower::database::profile::{discover_profiles, select_profile};
+use tower::config::Config;
+use tower::transport::{HttpTransport, Options, RetryPolicy, RetryTransport, Transport};
+use tower::proxy::Proxy;
+use tower::cassette::{RecordingTransport, ReplayTransport};
+use tower::week::{weeks_in_year, Clock, SystemClock, Week};
+use tower::{editor, markdown, report};
 
 struct Tower {
-    client: Client,
-    tid: String,
-    uid: String,
-    conn_guid: String,
-    weekly_info: Vec<(String, String, String)>,
+    client: TowerClient,
+    weekly_info: Vec<WeeklyField>,
+    // in Markdown, converted to html on submit
     answers: Vec<String>,
-    headers: Headers,
-    member_list: HashMap<String, String>,
+    // html of answers already on tower.im, sent as is if left unchanged
+    fetched: Vec<String>,
     disable_confirm: bool,
+    // send empty answers as blank html, so they count as filled
+    fill_blank: bool,
+    // fill in answers in $EDITOR instead of stdin
+    use_editor: bool,
+    // week of the report to show, fill in and submit
+    week: Week,
+    // today and the time of overtime records come from here
+    clock: Box<dyn Clock>,
 }
 
 impl Tower {
-    pub fn new() -> Tower {
+    pub fn new(client: TowerClient) -> Tower {
         Tower {
-            client: Client::with_connector(HttpsConnector::new(NativeTlsClient::new().unwrap())),
-            tid: String::new(),
-            uid: String::new(),
-            conn_guid: String::new(),
-            weekly_info: Vec::<(String, String, String)>::new(),
+            client,
+            weekly_info: Vec::<WeeklyField>::new(),
             answers: Vec::<String>::new(),
-            headers: Headers::new(),
-            member_list: HashMap::with_capacity(200),
+            fetched: Vec::<String>::new(),
             disable_confirm: false,
+            fill_blank: false,
+            use_editor: false,
+            week: Week::current(&SystemClock),
+            clock: Box::new(SystemClock),
         }
     }
 
-    pub fn load_sqlite<T: AsRef<str>>(&mut self, file: T, db_type: SqliteType) -> bool {
-
-        debug!("load sqlite from: {}", file.as_ref());
-
-        let mut sc = SqliteCookie::new(file, db_type);
-        let _ = sc.read_data();
-
-        // set headers
-        self.tid = sc.team_id().clone();
-
-        let token = sc.token();
+    pub fn clock(&self) -> &dyn Clock {
+        self.clock.as_ref()
+    }
 
-        let mut header_cookie = Cookie(vec![]);
-        header_cookie.0.push(format!("remember_team_guid={}", self.tid));
-        header_cookie.0.push(format!("remember_token={}", token));
+    /// Use the report of `week` instead of the current one.
+    pub fn select_week(&mut self, week: Week) {
+        self.week = week;
+    }
 
-        let header_host = Host {
-            hostname: "tower.im".to_owned(),
-            port: None,
-        };
-        let header_ua = UserAgent("Mozilla/5.0 (X11; Linux x86_64; rv:51.0) Gecko/20100101 \
-                                   Firefox/51.0"
-            .to_owned());
-
-        // set headers
-        {
-            let mut headers = &mut self.headers;
-            headers.set(header_host);
-            headers.set(header_ua);
-            headers.set(header_cookie);
-        }
+    pub async fn show_weekly_reports(&mut self) -> tower::Result<()> {
 
-        // members page
-        let url = &self.members_url(&self.tid);
-        let request = self.client.get(url).headers(self.headers.clone());
-        let mut response = request.send().unwrap();
-        let mut content = String::new();
-        let _ = response.read_to_string(&mut content);
-
-        // get extra cookies
-        for cookie in &response.headers.get::<SetCookie>().unwrap().0 {
-            if cookie.starts_with("_tower2_session") {
-                let i = cookie.split(';').next().unwrap();
-                info!("find cookie: {}", i);
-                self.headers.get_mut::<Cookie>().unwrap().push(i.to_owned());
-                break;
-            }
-        }
+        let report = self.get_weekly_reports().await?;
+        print_weekly_report(&report);
 
-        // get csrf-token
-        let re = Regex::new(r#"content="([^"]+)" name="csrf-token""#).unwrap();
-        let caps = re.captures(&content).unwrap();
-        let header_csrf_token = XCSRFToken(caps.get(1).unwrap().as_str().to_owned());
-        self.headers.set(header_csrf_token);
-
-        // get conn-guid
-        let re = Regex::new(r#"id="conn-guid" value="(\w+)"#).unwrap();
-        let caps = re.captures(&content).unwrap_or_else(|| panic!("find conn-guid failed"));
-        self.conn_guid = caps.get(1).unwrap().as_str().to_owned();
-
-        // get uid
-        let re = Regex::new(r#"id="member-guid" value="(\w+)"#).unwrap();
-        let caps = re.captures(&content).unwrap_or_else(|| panic!("find member-guid failed"));
-        self.uid = caps.get(1).unwrap().as_str().to_owned();
-
-        // find member uid list
-        let re = Regex::new(r#"href="/members/(\w+)"[\s\S]+?member-nickname">([^<]+)"#).unwrap();
-        for caps in re.captures_iter(&content) {
-            let name = caps.get(2).unwrap().as_str().to_owned();
-            let uid = caps.get(1).unwrap().as_str().to_owned();
-
-            info!("got member: {} {}", name, uid);
-            self.member_list.insert(name, uid);
-        }
-
-        true
+        Ok(())
     }
 
-    pub fn show_weekly_reports(&self) {
+    pub async fn show_calendar_info(&mut self) -> tower::Result<()> {
 
-        let (titles, contents) = self.get_weekly_reports();
+        let content = self.client.calendar_events().await?;
 
-        if titles.len() == 0 {
-            println!("your weekly reports is empty.");
-            return;
-        }
+        // TODO: process content
+        println!("{}", content);
+        // println!("{:?}", self.current_time_formatted());
 
-        for i in 0..titles.len() {
-            println!("{}", titles[i]);
-            println!("{}", contents[i]);
-        }
+        Ok(())
     }
 
-    pub fn show_calendar_info(&self) {
+    // weekly reports and calendar, fetched in parallel
+    pub async fn show_overview(&mut self) -> tower::Result<()> {
 
-        let url = format!("https://tower.im/members/{}/calendar_events/", self.uid);
-        let request = self.client.get(&url).headers(self.headers.clone());
-        let mut response = request.send().unwrap();
-        let mut content = String::new();
-        let _ = response.read_to_string(&mut content);
+        let Overview { report, calendar } = self.client.overview(self.week).await?;
+        print_weekly_report(&report);
+        println!("{}", calendar);
 
-        // TODO: process content
-        println!("{}", content);
-        // println!("{:?}", self.current_time_formatted());
+        Ok(())
     }
 
-    pub fn send_weekly_reports(&mut self) {
+    pub async fn send_weekly_reports(&mut self) -> tower::Result<()> {
 
         if self.weekly_info.is_empty() {
-            self.load_weekly_info();
+            self.load_weekly_info().await?;
         }
 
-        let year = self.current_year();
-        let week = self.current_week();
+        // the editor starts from what is on tower.im already
+        if self.use_editor && self.answers.is_empty() {
+            self.answers = self.get_weekly_answers_markdown().await?;
+            self.get_weekly_answers()?;
+        }
+
+        // answers beyond the form, like those of a removed field, are dropped
+        self.answers.truncate(self.weekly_info.len());
 
         // check answers match fields
-        while self.answers.is_empty() || !self.confirm_answers() {
-            self.get_weekly_answers();
+        while self.answers.is_empty() || !self.confirm_answers()? {
+            self.get_weekly_answers()?;
         }
 
-        let mut answers = Array::new();
-        for (i, ans) in self.answers.iter().enumerate() {
+        let answers = self.answers_html();
+        self.client.submit_weekly_report(self.week, &self.weekly_info, &answers).await?;
+        println!("Post weekly report success.");
 
-            let mut object = Object::new();
-            object.insert("content".to_owned(), Json::String(ans.to_owned()));
-            object.insert(self.weekly_info[i].0.clone(),
-                          Json::String(self.weekly_info[i].1.clone()));
+        self.show_weekly_reports().await
+    }
 
-            answers.push(Json::Object(object));
-        }
+    /// Submit answers in a report file, see `tower::report` for the formats.
+    pub async fn send_weekly_reports_from<P: AsRef<Path>>(&mut self,
+                                                           path: P)
+                                                           -> tower::Result<()> {
 
-        let answers = encode(&answers).unwrap();
-        let send_data = format!("conn_guid={}&data={}", self.conn_guid, answers);
-
-        let mut headers = self.headers.clone();
-        headers.set(POSTAccept("application/json, text/javascript, */*; q=0.01".to_owned()));
-        self.headers = headers;
-
-        let url = format!("https://tower.im/members/{}/weekly_reports/{}-{}",
-                          self.uid,
-                          year,
-                          week);
-        let request = self.client.post(&url).body(&send_data).headers(self.headers.clone());
-        let mut response = request.send().unwrap();
-        let mut result = String::new();
-        let _ = response.read_to_string(&mut result);
-        // println!("{}", result);
-        // let json: Json::Object = decode(&result).unwrap();
-        let json: Json = result.parse().unwrap();
-        let json = json.as_object().unwrap();
-
-        if json.contains_key("success") && json["success"] == Json::Boolean(true) {
-            println!("Post weekly report success.");
-        } else {
-            debug!("{}", result);
-            println!("Post weekly report fail.")
+        if self.weekly_info.is_empty() {
+            self.load_weekly_info().await?;
         }
 
-        self.show_weekly_reports();
+        // every field is checked to be covered
+        self.answers = report::load(path, &self.weekly_info)?;
+        self.send_weekly_reports().await
     }
 
-    pub fn send_today_reports(&mut self) {
+    pub async fn send_today_reports(&mut self) -> tower::Result<()> {
 
-        let day_of_week: usize = strftime("%u", &now()).unwrap().parse().unwrap();
-        self.send_day_reports(day_of_week - 1);
+        // tm_wday starts from sunday
+        let day_of_week = (self.clock.now().tm_wday + 6) % 7;
+        self.send_day_reports(day_of_week as usize).await
     }
 
-    pub fn send_overtime_record<T: AsRef<str>>(&mut self, title: T, cc_name: T) {
-        self.send_overtime_internal(title, cc_name);
+    pub async fn send_overtime_record<T: AsRef<str>>(&mut self,
+                                                     title: T,
+                                                     cc_name: T)
+                                                     -> tower::Result<()> {
+        self.send_overtime_internal(title, cc_name).await
     }
 
-    pub fn send_fake_reports(&mut self) {
-
-        self.load_weekly_info();
-        self.answers = self.get_weekly_reports().1;
+    pub async fn send_fake_reports(&mut self) -> tower::Result<()> {
 
-        for i in self.answers.iter_mut() {
-            if i.is_empty() {
-                i.push_str("<p></p><br/>");
-            }
-        }
+        self.load_weekly_info().await?;
+        self.answers = self.get_weekly_answers_markdown().await?;
 
         for _ in self.answers.len()..self.weekly_info.len() {
-            self.answers.push("<p></p><br/>".to_owned());
+            self.answers.push(String::new());
         }
 
-        self.send_weekly_reports();
+        self.fill_blank = true;
+        self.send_weekly_reports().await
     }
 
     pub fn disable_confirm(&mut self) {
         self.disable_confirm = true;
     }
 
-    fn send_overtime_internal<T: AsRef<str>>(&mut self, title: T, cc_name: T) {
-        let cc_name = cc_name.as_ref();
-        let cc_guid = match self.member_list.get(cc_name) {
-            Some(guid) => guid,
-            _ => {
-                println!("User {} not exist!", cc_name);
-                return;
-            }
-        };
-
-        let day_string = strftime("%Y-%m-%d", &now()).unwrap();
-        let (cur_hour, cur_min) = self.current_time_formatted();
-
-        let post_url = format!("https://tower.im/teams/{}/calendar_events/", self.tid);
-        let start_time = format!("{}+17%3A30%3A00", day_string);
-        let end_time = format!("{}+{}%3A{}%3A00", day_string, cur_hour, cur_min);
-        let post_body = format!("conn_guid={}&content={}&starts_at={}&ends_at={}&is_show_creator=true&caleventable_type=Calendar&caleventable_guid=b96e5a357a884c7e8c5c2ab12858dd02&schedule_interval=1",
-                                self.conn_guid,
-                                title.as_ref(),
-                                start_time,
-                                end_time);
-
-        // let post_body = format!("conn_guid={}&content={}&starts_at={}&ends_at={}&schedule_until={}&schedule_every=0&location=&remind_time=&is_show_creator=true&member_guids=&caleventable_type=Calendar&caleventable_guid=b96e5a357a884c7e8c5c2ab12858dd02&start=2016-10-31&end=2016-12-04&schedule_interval=1", self.conn_guid, content, start_time, end_time, schedule_until);
-
-        // println!("{}, {}", start_time, end_time);
-
-        // let post_body = format!("conn_guid={}&content={}&starts_at=2017-01-06+09%3A00%3A00&ends_at=2017-01-06+17%3A00%3A00&schedule_until=2017-01-06+23%3A59%3A59&schedule_every=0&location=&remind_time=&is_show_creator=true&member_guids=&caleventable_type=Calendar&caleventable_guid=b96e5a357a884c7e8c5c2ab12858dd02&start=2016-12-26&end=2017-02-05&schedule_interval=1",
-        //                     self.conn_guid,
-        //                     title.as_ref()
-        //                     );
-
-        // post data and check result
-        let response = self.post_data(post_url, post_body);
-        let result: Json = response.parse().unwrap();
-        let object = result.as_object().unwrap();
-
-        if object.get("success") != Some(&Json::Boolean(true)) {
-            info!("post overtime error:");
-            info!("{}", result);
-            return;
-        }
-
-        if let Some(&Json::String(ref url)) = object.get("url") {
-            // let content = "conn_guid=51586e27839ff9f7766f16bad29b49c4&comment_content=%3Cp%3E%3Ca+href%3D%22%2Fmembers%2F83555be08c1a4912a1f875636afa3f52%22+data-mention%3D%22true%22%3E%40%E5%BC%A0%E7%BB%A7%E5%BE%B7%3C%2Fa%3E%26nbsp%3B%3Cbr%3E%3C%2Fp%3E&is_html=1&cc_guids=83555be08c1a4912a1f875636afa3f52";
-
-            let comment_content = format!("<p><a href=\"/members/{}\" \
-                                           data-mention=\"true\">@{}</a></p>",
-                                          cc_guid,
-                                          cc_name);
-            let content = format!("conn_guid={}&comment_content={}&is_html=1&cc_guids={}",
-                                  self.conn_guid,
-                                  comment_content,
-                                  self.member_list.get(cc_name).unwrap());
-
-            let _ = self.post_data(format!("https://tower.im{}/comments", url), content);
-
-            let url = format!("https://tower.im{}", url);
-            println!("send overtime finished, url is {}", url);
-
-            Command::new("gio")
-                .arg("open")
-                .arg(url)
-                .exec();
-        } else {
-            println!("send overtime failed.");
-        }
+    pub fn use_editor(&mut self) {
+        self.use_editor = true;
     }
 
-    fn post_data<U: AsRef<str>, B: AsRef<str>>(&self, url: U, body: B) -> String {
-        let request =
-            self.client.post(url.as_ref()).body(body.as_ref()).headers(self.headers.clone());
-        let mut response = request.send().unwrap();
-        assert_eq!(StatusCode::Ok, response.status);
-        let mut result = String::new();
-        let _ = response.read_to_string(&mut result);
+    async fn send_overtime_internal<T: AsRef<str>>(&mut self,
+                                                   title: T,
+                                                   cc_name: T)
+                                                   -> tower::Result<()> {
+        let cc = self.client.member(cc_name).await?;
 
-        result
-    }
+        let mut starts_at = self.clock.now();
+        starts_at.tm_hour = 17;
+        starts_at.tm_min = 30;
+        starts_at.tm_sec = 0;
 
-    fn get_data<T: AsRef<str>>(&self, url: T) -> String {
-        let req = self.client.get(url.as_ref()).headers(self.headers.clone());
-        let mut response = req.send().unwrap();
-        assert_eq!(StatusCode::Ok, response.status);
-        let mut result = String::new();
-        let _ = response.read_to_string(&mut result);
+        let (cur_hour, cur_min) = self.current_time_formatted();
+        let mut ends_at = self.clock.now();
+        ends_at.tm_hour = cur_hour;
+        ends_at.tm_min = cur_min;
+        ends_at.tm_sec = 0;
 
-        result
-    }
+        let event = self.client.create_calendar_event(title, &starts_at, &ends_at).await?;
 
-    fn load_weekly_info(&mut self) {
+        let comment_content = format!("<p>{}</p>", cc.mention_html());
+        self.client.create_comment(&event.url, &comment_content, &[cc]).await?;
 
-        assert!(self.weekly_info.is_empty());
+        let url = format!("{}{}", self.client.base_url(), event.url);
+        println!("send overtime finished, url is {}", url);
 
-        let year = self.current_year();
-        let week = self.current_week();
+        // opening the browser is a convenience, the event is created already
+        match Command::new("gio").arg("open").arg(&url).status() {
+            Ok(status) if !status.success() => warn!("gio open {} returns {}", url, status),
+            Err(e) => warn!("run gio open {} failed: {}", url, e),
+            _ => {}
+        }
 
-        // get weekly info
-        let url = format!("https://tower.im/members/{}/weekly_reports/{}-{}/edit?conn_guid={}",
-                          self.uid,
-                          year,
-                          week,
-                          self.conn_guid);
+        Ok(())
+    }
 
-        let result = self.get_data(&url);
-        let json: Json = result.parse().unwrap();
-        let result = json["html"].as_string().unwrap();
+    async fn load_weekly_info(&mut self) -> tower::Result<()> {
 
-        let ref mut fields = self.weekly_info;
-        // let mut fields = Vec::<(&str, &str, &str)>::new();
-        let re = Regex::new(r#"<input.*?name="(.*?)".*?value="(.*?)".*?>\s*(.*?)\s*</div>"#)
-            .unwrap();
-        for caps in re.captures_iter(&result) {
-            let k = caps.get(1).unwrap().as_str();
-            let v = caps.get(2).unwrap().as_str();
-            let t = caps.get(3).unwrap().as_str();
-            fields.push((k.to_owned(), v.to_owned(), t.to_owned()));
+        if !self.weekly_info.is_empty() {
+            return Ok(());
         }
+
+        self.weekly_info = self.client.weekly_fields(self.week).await?;
+        Ok(())
     }
 
     // send spec day report, index is start with 0
-    fn send_day_reports(&mut self, index: usize) {
+    async fn send_day_reports(&mut self, index: usize) -> tower::Result<()> {
+
+        self.load_weekly_info().await?;
+        if index >= self.weekly_info.len() {
+            let what = format!("field of day {} in weekly report form", index + 1);
+            return Err(TowerError::Parse(what));
+        }
 
         println!("input your reports of day {}", index + 1);
         let mut ans = String::new();
         let _ = stdin().read_to_string(&mut ans);
 
-        let (_, mut answers) = self.get_weekly_reports();
+        let mut answers = self.get_weekly_answers_markdown().await?;
 
         while answers.len() <= index {
             answers.push(String::new());
@@ -394,68 +243,77 @@ impl Tower {
 
         answers[index] = ans;
         self.answers = answers;
-        self.send_weekly_reports();
+        self.send_weekly_reports().await
     }
 
-    fn get_weekly_reports(&self) -> (Vec<String>, Vec<String>) {
+    async fn get_weekly_reports(&mut self) -> tower::Result<WeeklyReport> {
 
-        let url = self.weekly_reports_url(&self.uid, &self.conn_guid);
-        let request = self.client.get(&url).headers(self.headers.clone());
-        let mut response = request.send().unwrap();
-        let mut content = String::new();
-        let _ = response.read_to_string(&mut content);
+        self.client.weekly_report(self.week).await
+    }
 
-        // search weekly_title
-        let mut titles = Vec::new();
-        let re = Regex::new(r#"<dt><i class="icon twr twr-quote-left"></i>([^<]+)</dt>"#).unwrap();
-        for caps in re.captures_iter(&content) {
-            titles.push(caps.get(1).unwrap().as_str().to_owned());
-        }
+    // answers already submitted, back in Markdown to edit
+    async fn get_weekly_answers_markdown(&mut self) -> tower::Result<Vec<String>> {
 
-        // search weekly_content
-        let mut contents = Vec::new();
-        let re = Regex::new(r#"<dd class="editor-style">(.*?)</dd>"#).unwrap();
-        for caps in re.captures_iter(&content) {
-            contents.push(caps.get(1).unwrap().as_str().to_owned());
-        }
+        self.fetched = self.get_weekly_reports().await?.contents();
+        Ok(self.fetched.iter().map(|x| markdown::to_markdown(x)).collect())
+    }
 
-        assert!(titles.len() == contents.len());
+    // Markdown does not hold every html, like tables, so fetched answers
+    // the user did not edit are sent back untouched
+    fn answers_html(&self) -> Vec<String> {
+        self.answers
+            .iter()
+            .enumerate()
+            .map(|(i, answer)| {
+                let html = match self.fetched.get(i) {
+                    Some(html) if *answer == markdown::to_markdown(html) => html.clone(),
+                    _ => markdown::to_html(answer, self.client.members()),
+                };
 
-        (titles, contents)
+                if html.trim().is_empty() && self.fill_blank {
+                    markdown::BLANK_HTML.to_owned()
+                } else {
+                    html
+                }
+            })
+            .collect()
     }
 
-    fn confirm_answers(&self) -> bool {
-        assert!(self.weekly_info.len() >= self.answers.len());
-
+    fn confirm_answers(&self) -> tower::Result<bool> {
         if self.disable_confirm {
-            return true;
+            return Ok(true);
         }
 
-        print!("\n");
+        println!();
 
         // print user answers
-        for (i, ref answer) in self.answers.iter().enumerate() {
-            println!("{}:\n{}\n\n", self.weekly_info[i].2, answer);
+        for (field, answer) in self.weekly_info.iter().zip(&self.answers) {
+            println!("{}:\n{}\n\n", field.title, answer);
         }
 
-        if self.weekly_info.len() != self.answers.len() {
+        if self.weekly_info.len() > self.answers.len() {
             println!("some fields not filled:");
 
-            for i in self.answers.len()..self.weekly_info.len() {
-                println!("{}", self.weekly_info[i].2);
+            for field in &self.weekly_info[self.answers.len()..] {
+                println!("{}", field.title);
             }
         }
 
         ask_question("Submit your answers?", !self.answers.is_empty())
     }
 
-    fn get_weekly_answers(&mut self) {
+    fn get_weekly_answers(&mut self) -> tower::Result<()> {
 
-        print!("\n");
+        if self.use_editor {
+            self.answers = editor::edit_answers(&self.weekly_info, &self.answers)?;
+            return Ok(());
+        }
+
+        println!();
 
         let exist_len = self.answers.len();
-        for (i, &(_, _, ref title)) in self.weekly_info.iter().enumerate() {
-            println!("\n{}:", title);
+        for (i, field) in self.weekly_info.iter().enumerate() {
+            println!("\n{}:", field.title);
 
             let overflow = i >= exist_len;
 
@@ -466,7 +324,7 @@ impl Tower {
 
             // get user answer
             let mut answer = String::new();
-            stdin().read_to_string(&mut answer).unwrap();
+            stdin().read_to_string(&mut answer)?;
             let answer = answer.trim();
 
             let e = answer.is_empty();
@@ -478,46 +336,60 @@ impl Tower {
             }
         }
 
-        assert!(self.weekly_info.len() == self.answers.len())
+        self.answers.truncate(self.weekly_info.len());
+        Ok(())
     }
 
-    fn weekly_reports_url<T: AsRef<str>>(&self, uid: T, conn_guid: T) -> String {
-        format!("https://tower.im/members/{}/weekly_reports/?conn_guid={}&pjax=1",
-                uid.as_ref(),
-                conn_guid.as_ref())
-    }
+    fn current_time_formatted(&self) -> (i32, i32) {
+        let tm = self.clock.now();
+        let hour = tm.tm_hour;
+        let minute = tm.tm_min;
+        let temp_min = (minute + 15) / 30 * 30;
+        let result_hour = hour + temp_min / 60;
+        let result_min = temp_min % 60;
 
-    // fn profile_url<T: AsRef<str>>(&self, uid: T) -> String {
-    //     format!("https://tower.im/members/{}/?me=1", uid.as_ref())
-    // }
+        (result_hour, result_min)
+    }
+}
 
-    fn members_url<T: AsRef<str>>(&self, tid: T) -> String {
-        format!("https://tower.im/teams/{}/members/", tid.as_ref())
+fn is_year(value: String) -> std::result::Result<(), String> {
+    match value.parse::<i32>() {
+        Ok(year) if year >= 2000 => Ok(()),
+        _ => Err(format!("invalid year {}", value)),
     }
+}
 
-    fn current_year(&self) -> i32 {
-        now().tm_year + 1900
+fn is_week(value: String) -> std::result::Result<(), String> {
+    match value.parse::<u32>() {
+        Ok(week) if (1..=53).contains(&week) => Ok(()),
+        _ => Err(format!("invalid week {}, should be 1 to 53", value)),
     }
+}
 
-    fn current_week(&self) -> String {
-        strftime("%W", &now()).unwrap()
+fn is_seconds(value: String) -> std::result::Result<(), String> {
+    match value.parse::<u64>() {
+        Ok(secs) if secs >= 1 => Ok(()),
+        _ => Err(format!("invalid seconds {}, should be a positive integer", value)),
     }
+}
 
-    fn current_time_formatted(&self) -> (i32, i32) {
-        let tm = now();
-        let hour = tm.tm_hour;
-        let minute = tm.tm_min;
-        let temp_min = (minute + 15) / 30 * 30;
-        let result_hour = hour + temp_min / 60;
-        let result_min = temp_min % 60;
+fn print_weekly_report(report: &WeeklyReport) {
 
-        (result_hour, result_min)
+    if report.is_empty() {
+        println!("your weekly reports is empty.");
+        return;
+    }
+
+    for section in &report.sections {
+        println!("{}", section.title);
+        println!("{}", markdown::to_markdown(&section.content));
     }
 }
 
-fn ask_question<T: AsRef<str>>(q: T, default: bool) -> bool {
+// Err(Aborted) when stdin is closed, like ctrl-d
+fn ask_question<T: AsRef<str>>(q: T, default: bool) -> tower::Result<bool> {
 
-    if default == true {
+    if default {
         print!("{} [Y/n]:", q.as_ref());
     } else {
         print!("{} [y/N]:", q.as_ref());
@@ -525,53 +397,323 @@ fn ask_question<T: AsRef<str>>(q: T, default: bool) -> bool {
     let _ = stdout().flush();
 
     let mut result = String::new();
-    stdin().read_line(&mut result).unwrap();
+    if stdin().read_line(&mut result)? == 0 {
+        return Err(TowerError::Aborted);
+    }
     let result = result.trim().to_lowercase();
 
     match result.as_str() {
-        "y" | "yes" => true,
-        "n" | "no" => false,
-        _ => default,
+        "y" | "yes" => Ok(true),
+        "n" | "no" => Ok(false),
+        _ => Ok(default),
+    }
+}
+
+fn read_input<T: AsRef<str>>(q: T) -> tower::Result<String> {
+
+    print!("{} ", q.as_ref());
+    let _ = stdout().flush();
+
+    let mut result = String::new();
+    if stdin().read_line(&mut result)? == 0 {
+        return Err(TowerError::Aborted);
+    }
+
+    Ok(result.trim().to_owned())
+}
+
+// like `read_input`, but what is typed is not echoed
+fn read_password<T: AsRef<str>>(q: T) -> tower::Result<String> {
+
+    let fd = libc::STDIN_FILENO;
+    let mut term: libc::termios = unsafe { mem::zeroed() };
+
+    // not a terminal, like a pipe, there is nothing to hide
+    if unsafe { libc::tcgetattr(fd, &mut term) } != 0 {
+        return read_input(q);
+    }
+
+    // the newline is still echoed, so output goes on the next line
+    let mut silent = term;
+    silent.c_lflag &= !libc::ECHO;
+    silent.c_lflag |= libc::ECHONL;
+
+    unsafe { libc::tcsetattr(fd, libc::TCSANOW, &silent) };
+    let result = read_input(q);
+    unsafe { libc::tcsetattr(fd, libc::TCSANOW, &term) };
+
+    result
+}
+
+#[cfg(feature = "keyring")]
+fn save_keyring(client: &TowerClient) {
+
+    let team_id = client.cookie("remember_team_guid").unwrap_or(String::new());
+    let token = client.cookie("remember_token").unwrap_or(String::new());
+
+    if let Err(e) = KeyringCookie::store(team_id, token) {
+        println!("save cookies into keyring failed: {}", e);
     }
 }
 
-fn search_cookie_sqlite_chrome() -> Option<String> {
-    None
-    // Some("/home/.config/google-chrome/Profile 1/Cookies".to_owned())
+fn seconds_of(matches: &ArgMatches, name: &str) -> Option<u64> {
+    matches.value_of(name).and_then(|x| x.parse().ok())
 }
 
-fn search_cookie_sqlite_firefox() -> Option<String> {
+// find cookies and restore the session
+async fn load_client(client: &mut TowerClient, matches: &ArgMatches<'_>) -> tower::Result<()> {
+
+    let chrome_key = matches.value_of("chrome_key")
+        .map(|x| x.to_owned())
+        .or(var("TOWER_CHROME_KEY").ok());
+    let chrome_key = chrome_key.as_deref();
+
+    // cookies given explicitly take precedence over everything
+    let source: Option<Box<dyn CookieSource>> = match matches.value_of("cookies") {
+        Some(file) if file.ends_with(".har") => Some(Box::new(HarCookie::new(file))),
+        Some(file) => Some(Box::new(NetscapeCookie::new(file))),
+        None => HeaderCookie::from_env().map(|x| Box::new(x) as Box<dyn CookieSource>),
+    };
+
+    if let Some(mut source) = source {
+        client.load_cookies(source.as_mut()).await?;
+        client.save_session();
+        // the file may be exported again once the session expires
+        client.add_reauth_source(source);
+        return Ok(());
+    }
+
+    let profiles = discover_profiles();
+    let browser = matches.value_of("browser").and_then(Browser::from_name);
+    let profile = select_profile(&profiles,
+                                 browser.as_ref(),
+                                 matches.value_of("profile"),
+                                 chrome_key);
+    if let (Some(name), None) = (matches.value_of("profile"), profile) {
+        return Err(TowerError::ProfileNotFound(name.to_owned()));
+    }
+
+    // where fresh cookies are read from once the session expires
+    #[cfg(feature = "keyring")]
+    client.add_reauth_source(Box::new(KeyringCookie::new()));
+    if let Some(p) = profile {
+        // `--browser` and `--profile` pick the session cache as well
+        client.set_session_source(p.cookie_path.to_string_lossy());
+
+        let mut source = SqliteCookie::new(p.cookie_path.to_string_lossy(), p.browser.db_type());
+        if let Some(key) = chrome_key {
+            source.set_chrome_key(key);
+        }
+        client.add_reauth_source(Box::new(source));
+    }
+    let credentials = (var("TOWER_EMAIL"), var("TOWER_PASSWORD"));
+    if let (Ok(email), Ok(password)) = (&credentials.0, &credentials.1) {
+        client.set_credentials(email, password);
+    }
 
-    let home = home_dir().unwrap();
-    let config_file = format!("{}/.mozilla/firefox/profiles.ini", home.display());
+    if !matches.is_present("refresh") && client.load_session() {
+        return Ok(());
+    }
 
-    let file = File::open(config_file);
-    for line in BufReader::new(&file.unwrap()).lines() {
+    #[cfg(feature = "keyring")]
+    {
+        match client.load_cookies(&mut KeyringCookie::new()).await {
+            Ok(_) => {
+                client.save_session();
+                return Ok(());
+            }
+            Err(e) => info!("load cookies from keyring failed: {}", e),
+        }
+    }
 
-        if line.is_err() {
-            continue;
+    match profile {
+        Some(p) => {
+            info!("use {} profile {}", p.browser.name(), p.name);
+            client.load_sqlite(p.cookie_path.to_string_lossy(),
+                               p.browser.db_type(),
+                               chrome_key)
+                .await?;
+        }
+        None => {
+            // no browser around, e.g. on servers or CI
+            match credentials {
+                (Ok(email), Ok(password)) => client.login(email, password).await?,
+                _ => return Err(TowerError::NotLoggedIn),
+            }
         }
+    }
+
+    client.save_session();
+    Ok(())
+}
+
+async fn run(matches: &ArgMatches<'_>) -> tower::Result<()> {
+
+    let base_url = matches.value_of("base_url")
+        .map(|x| x.to_owned())
+        .or(var("TOWER_BASE_URL").ok());
+    let base_url = base_url.unwrap_or("https://tower.im".to_owned());
+    let config = Config::load();
+
+    let mut options = Options::default();
+    if let Some(secs) = seconds_of(matches, "connect_timeout").or(config.connect_timeout()) {
+        options.timeouts.connect = Duration::from_secs(secs);
+    }
+    if let Some(secs) = seconds_of(matches, "timeout").or(config.timeout()) {
+        options.timeouts.read = Duration::from_secs(secs);
+    }
+    options.proxy = Proxy::from_env(&base_url);
+    if let Some(ref proxy) = options.proxy {
+        info!("use proxy {}", proxy);
+    }
+    options.ca_file = matches.value_of("ca_file").map(PathBuf::from);
+    let mut retry = RetryPolicy::default();
+    if let Some(retries) = config.retries() {
+        retry.retries = retries;
+    }
+
+    let network = || -> tower::Result<RetryTransport<HttpTransport>> {
+        Ok(RetryTransport::new(HttpTransport::with_options(&options)?, retry))
+    };
+    let cassette = (matches.value_of("record"), matches.value_of("replay"));
+    let transport: Box<dyn Transport> = match cassette {
+        (Some(file), _) => Box::new(RecordingTransport::new(network()?, file)),
+        (_, Some(file)) => Box::new(ReplayTransport::load(file)?),
+        _ => Box::new(network()?),
+    };
+    let mut client = TowerClient::with_transport(transport, base_url);
+    client.set_retry_policy(retry);
+
+    if let Some(matches) = matches.subcommand_matches("login") {
+        let email = match matches.value_of("email") {
+            Some(email) => email.to_owned(),
+            None => {
+                match var("TOWER_EMAIL") {
+                    Ok(email) => email,
+                    Err(_) => read_input("Email:")?,
+                }
+            }
+        };
+        let password = match var("TOWER_PASSWORD") {
+            Ok(password) => password,
+            Err(_) => read_password("Password:")?,
+        };
 
-        let l = line.unwrap();
-        if !l.starts_with("Path=") {
-            continue;
+        client.login(email, password).await?;
+        client.save_session();
+        #[cfg(feature = "keyring")]
+        save_keyring(&client);
+        println!("login success.");
+        return Ok(());
+    }
+
+    if matches.is_present("list_profiles") {
+        let chrome_key = matches.value_of("chrome_key")
+            .map(|x| x.to_owned())
+            .or(var("TOWER_CHROME_KEY").ok());
+        let chrome_key = chrome_key.as_deref();
+
+        for p in &discover_profiles() {
+            println!("{}\t{}{}\t{}\t{}",
+                     p.browser.name(),
+                     p.name,
+                     if p.is_default { " (default)" } else { "" },
+                     if p.has_token(chrome_key) { "logged in" } else { "-" },
+                     p.cookie_path.display());
         }
+        return Ok(());
+    }
+
+    load_client(&mut client, matches).await?;
 
-        let dir: Vec<&str> = l.split('=').collect();
-        let sqlite = format!("{}/.mozilla/firefox/{}/cookies.sqlite",
-                             home.display(),
-                             dir[1]);
-        if Path::new(&sqlite).exists() {
-            return Some(sqlite);
-        } else {
-            return None;
+    if matches.is_present("list_teams") {
+        let current = client.current_team().clone();
+        for team in client.teams().await? {
+            println!("{}\t{}{}",
+                     team.guid,
+                     team.name,
+                     if team.guid == current { " (current)" } else { "" });
         }
+        return Ok(());
+    }
+
+    if let Some(team) = matches.value_of("team").or(config.team()) {
+        client.switch_team(team).await?;
+    }
+
+    let mut tower = Tower::new(client);
+
+    if matches.is_present("confirm") {
+        tower.disable_confirm();
+    }
+
+    if matches.is_present("editor") {
+        tower.use_editor();
+    }
+
+    // a week not filled in yet, like last week on monday
+    let current = if matches.is_present("last_week") {
+        Week::last(tower.clock())
+    } else {
+        Week::current(tower.clock())
+    };
+    let year = matches.value_of("year").and_then(|x| x.parse().ok()).unwrap_or(current.year);
+    let week = matches.value_of("week").and_then(|x| x.parse().ok()).unwrap_or(current.week);
+    match Week::new(year, week) {
+        Some(week) => tower.select_week(week),
+        None => {
+            let msg = format!("week {} of {} does not exist, {} has {} weeks",
+                              week,
+                              year,
+                              year,
+                              weeks_in_year(year));
+            clap::Error::with_description(&msg, clap::ErrorKind::InvalidValue).exit();
+        }
+    }
+
+    // if matches.is_present("reports") {
+    // println!("{:?}", matches.value_of("reports"));
+    // }
+
+    if let Some(matches) = matches.subcommand_matches("weekly") {
+        if let Some(matches) = matches.subcommand_matches("submit") {
+            tower.send_weekly_reports_from(matches.value_of("from").unwrap()).await?;
+        }
+        return Ok(());
+    }
+
+    if matches.is_present("fake") {
+        tower.send_fake_reports().await?;
+    }
+
+    if matches.is_present("send") {
+        tower.send_weekly_reports().await?;
+    }
+
+    if matches.is_present("today") {
+        tower.send_today_reports().await?;
     }
 
-    None
+    match (matches.is_present("weekly"), matches.is_present("calendar")) {
+        (true, true) => tower.show_overview().await?,
+        (true, false) => tower.show_weekly_reports().await?,
+        (false, true) => tower.show_calendar_info().await?,
+        (false, false) => {}
+    }
+
+    if matches.is_present("overtime") {
+        let title = matches.value_of("title").unwrap_or("加班登记");
+        let cc_name = matches.value_of("cc_name").unwrap();
+
+        tower.send_overtime_record(title, cc_name).await?;
+    }
+
+    Ok(())
 }
 
-fn main() {
+// requests run concurrently on this thread, see `Transport`
+#[tokio::main(flavor = "current_thread")]
+async fn main() {
 
     // process command-line
     let matches = App::new("Tower")
@@ -618,55 +760,118 @@ fn main() {
                          //.takes_value(true)
                          //.default_value("")
                          //.help("Your reports content"))
+                    .arg(Arg::with_name("year")
+                         .long("year")
+                         .takes_value(true)
+                         .validator(is_year)
+                         .help("Year of the weekly report to show, fill in or send, \
+                                defaults to this year"))
+                    .arg(Arg::with_name("week")
+                         .long("week")
+                         .takes_value(true)
+                         .validator(is_week)
+                         .help("Week number of the weekly report to show, fill in or send, \
+                                defaults to this week"))
+                    .arg(Arg::with_name("last_week")
+                         .long("last-week")
+                         .conflicts_with_all(&["year", "week"])
+                         .help("Use the weekly report of last week"))
+                    .arg(Arg::with_name("editor")
+                         .short("e")
+                         .long("editor")
+                         .help("Fill in weekly report answers in $VISUAL or $EDITOR, all in \
+                                one file"))
                     .arg(Arg::with_name("confirm")
                          .short("y")
                          .help("Always say yes."))
+                    .arg(Arg::with_name("chrome_key")
+                         .long("chrome-key")
+                         .takes_value(true)
+                         .help("Keyring password used to decrypt chrome cookies, \
+                                also read from TOWER_CHROME_KEY"))
+                    .arg(Arg::with_name("browser")
+                         .long("browser")
+                         .takes_value(true)
+                         .possible_values(&["firefox", "chrome", "chromium", "brave",
+                                            "vivaldi", "edge"])
+                         .help("Read cookies from this browser only"))
+                    .arg(Arg::with_name("profile")
+                         .long("profile")
+                         .takes_value(true)
+                         .help("Read cookies from this browser profile"))
+                    .arg(Arg::with_name("list_profiles")
+                         .long("list-profiles")
+                         .help("List browser profiles and which ones are logged in to tower.im"))
+                    .arg(Arg::with_name("cookies")
+                         .long("cookies")
+                         .takes_value(true)
+                         .help("Read cookies from a cookies.txt or .har file, a raw Cookie \
+                                header is also read from TOWER_COOKIE"))
+                    .arg(Arg::with_name("team")
+                         .long("team")
+                         .takes_value(true)
+                         .help("Team name or guid to use, defaults to `team` in config"))
+                    .arg(Arg::with_name("list_teams")
+                         .long("list-teams")
+                         .help("List teams you belong to"))
+                    .arg(Arg::with_name("refresh")
+                         .long("refresh")
+                         .help("Ignore the cached session and read cookies again"))
+                    .arg(Arg::with_name("base_url")
+                         .long("base-url")
+                         .takes_value(true)
+                         .help("Site to talk to instead of https://tower.im, like a \
+                                tower-mock-server, also read from TOWER_BASE_URL"))
+                    .arg(Arg::with_name("timeout")
+                         .long("timeout")
+                         .takes_value(true)
+                         .validator(is_seconds)
+                         .help("Seconds to wait for a response, defaults to 30"))
+                    .arg(Arg::with_name("connect_timeout")
+                         .long("connect-timeout")
+                         .takes_value(true)
+                         .validator(is_seconds)
+                         .help("Seconds to wait for a connection, defaults to 10"))
+                    .arg(Arg::with_name("ca_file")
+                         .long("ca-file")
+                         .takes_value(true)
+                         .help("Also trust the root certificates in this pem or der file, \
+                                like the one of a corporate proxy"))
+                    .arg(Arg::with_name("record")
+                         .long("record")
+                         .takes_value(true)
+                         .conflicts_with("replay")
+                         .help("Save requests and responses to a cassette file, with \
+                                cookies and tokens redacted"))
+                    .arg(Arg::with_name("replay")
+                         .long("replay")
+                         .takes_value(true)
+                         .help("Answer requests from a cassette file instead of network"))
+                    .subcommand(SubCommand::with_name("login")
+                         .about("Sign in with email and password, no browser needed")
+                         .arg(Arg::with_name("email")
+                              .long("email")
+                              .takes_value(true)
+                              .help("Account email, also read from TOWER_EMAIL")))
+                    .subcommand(SubCommand::with_name("weekly")
+                         .about("Weekly reports, pick the week with --year, --week or \
+                                 --last-week before the subcommand")
+                         .setting(AppSettings::SubcommandRequiredElseHelp)
+                         .subcommand(SubCommand::with_name("submit")
+                              .about("Submit a weekly report from a file, use -y to skip \
+                                      the confirmation")
+                              .arg(Arg::with_name("from")
+                                   .long("from")
+                                   .takes_value(true)
+                                   .required(true)
+                                   .help("Markdown file with a heading per question, or \
+                                          YAML or JSON mapping questions to Markdown answers"))))
                     .get_matches();
 
     env_logger::init().unwrap();
 
-    let mut tower = Tower::new();
-
-    if let Some(file) = search_cookie_sqlite_chrome() {
-        tower.load_sqlite(file, SqliteType::Chrome);
-    } else if let Some(file) = search_cookie_sqlite_firefox() {
-        tower.load_sqlite(file, SqliteType::Firefox);
-    } else {
-        panic!("can't load cookies");
-    }
-
-    if matches.is_present("confirm") {
-        tower.disable_confirm();
-    }
-
-    // if matches.is_present("reports") {
-    // println!("{:?}", matches.value_of("reports"));
-    // }
-
-    if matches.is_present("fake") {
-        tower.send_fake_reports();
-    }
-
-    if matches.is_present("send") {
-        tower.send_weekly_reports();
-    }
-
-    if matches.is_present("today") {
-        tower.send_today_reports();
-    }
-
-    if matches.is_present("weekly") {
-        tower.show_weekly_reports();
-    }
-
-    if matches.is_present("calendar") {
-        tower.show_calendar_info();
-    }
-
-    if matches.is_present("overtime") {
-        let title = matches.value_of("title").unwrap_or("加班登记");
-        let cc_name = matches.value_of("cc_name").unwrap();
-
-        tower.send_overtime_record(title, cc_name);
+    if let Err(e) = run(&matches).await {
+        let _ = writeln!(stderr(), "{}", e);
+        exit(e.exit_code());
     }
 }
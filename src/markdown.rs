@@ -0,0 +1,433 @@
+
+//! Convert between the Markdown users type and the html of the tower.im editor.
+//!
+//! Supported are paragraphs, line breaks, headings, flat lists, quotes, code,
+//! bold, italic, links, images and `@nickname` mentions of team members:
+//!
+//! ```ignore
+//! let html = to_html("- fixed **login**\n- reviewed @sbw", members);
+//! // <ul><li>fixed <strong>login</strong></li><li>reviewed <a href="/members/...
+//! assert_eq!(to_markdown(&html), "- fixed **login**\n- reviewed @sbw");
+//! ```
+
+use regex::Regex;
+
+use scraper::{ElementRef, Html, Node};
+
+use crate::types::Member;
+
+/// An answer which looks empty on tower.im, but is not empty for the robot
+/// checking whether reports are filled.
+pub const BLANK_HTML: &str = "<p></p><br/>";
+
+/// Escape `text` to put it in html, as text or an attribute value.
+pub fn escape(text: &str) -> String {
+    text.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+}
+
+// html blocks built line by line
+struct Blocks<'a> {
+    members: &'a [Member],
+    html: String,
+    paragraph: Vec<String>,
+    // tag and items of the open list
+    list: Option<(&'static str, Vec<String>)>,
+    quote: Vec<String>,
+}
+
+impl<'a> Blocks<'a> {
+    fn flush(&mut self) {
+        if !self.paragraph.is_empty() {
+            self.html.push_str(&format!("<p>{}</p>", self.paragraph.join("<br/>")));
+            self.paragraph.clear();
+        }
+        if let Some((tag, items)) = self.list.take() {
+            let items: Vec<String> = items.iter().map(|x| format!("<li>{}</li>", x)).collect();
+            self.html.push_str(&format!("<{}>{}</{}>", tag, items.concat(), tag));
+        }
+        if !self.quote.is_empty() {
+            self.html.push_str(&format!("<blockquote>{}</blockquote>", self.quote.join("<br/>")));
+            self.quote.clear();
+        }
+    }
+
+    fn list_item(&mut self, tag: &'static str, text: &str) {
+        // paragraphs and quotes end open lists, so only the list is left
+        match self.list {
+            Some((open, _)) if open == tag => {}
+            _ => self.flush(),
+        }
+
+        let item = inline(text, self.members);
+        match self.list {
+            Some((_, ref mut items)) => items.push(item),
+            None => self.list = Some((tag, vec![item])),
+        }
+    }
+}
+
+fn code_block(lines: &[&str]) -> String {
+    format!("<pre><code>{}</code></pre>", escape(&lines.join("\n")))
+}
+
+/// Html for the tower.im editor from `markdown`, `@nickname` of `members`
+/// becomes a mention.
+pub fn to_html(markdown: &str, members: &[Member]) -> String {
+    let heading_re = Regex::new(r"^(#{1,6})\s+(.*?)[\s#]*$").unwrap();
+    let bullet_re = Regex::new(r"^\s*[-*+]\s+(.*)$").unwrap();
+    let number_re = Regex::new(r"^\s*\d+[.)]\s+(.*)$").unwrap();
+    let quote_re = Regex::new(r"^\s*>\s?(.*)$").unwrap();
+
+    let mut blocks = Blocks {
+        members,
+        html: String::new(),
+        paragraph: vec![],
+        list: None,
+        quote: vec![],
+    };
+    let mut code: Option<Vec<&str>> = None;
+
+    for line in markdown.lines() {
+        if line.trim_start().starts_with("```") {
+            match code.take() {
+                Some(lines) => blocks.html.push_str(&code_block(&lines)),
+                None => {
+                    blocks.flush();
+                    code = Some(vec![]);
+                }
+            }
+            continue;
+        }
+        if let Some(ref mut lines) = code {
+            lines.push(line);
+            continue;
+        }
+
+        if line.trim().is_empty() {
+            blocks.flush();
+        } else if let Some(caps) = heading_re.captures(line) {
+            blocks.flush();
+            let level = caps[1].len();
+            let text = inline(&caps[2], members);
+            blocks.html.push_str(&format!("<h{}>{}</h{}>", level, text, level));
+        } else if let Some(caps) = bullet_re.captures(line) {
+            blocks.list_item("ul", &caps[1]);
+        } else if let Some(caps) = number_re.captures(line) {
+            blocks.list_item("ol", &caps[1]);
+        } else if let Some(caps) = quote_re.captures(line) {
+            if blocks.quote.is_empty() {
+                blocks.flush();
+            }
+            blocks.quote.push(inline(caps[1].trim(), members));
+        } else if blocks.list.is_some() && line.starts_with(char::is_whitespace) {
+            // indented lines continue the last item
+            let text = inline(line.trim(), members);
+            if let Some((_, ref mut items)) = blocks.list {
+                if let Some(item) = items.last_mut() {
+                    item.push_str("<br/>");
+                    item.push_str(&text);
+                }
+            }
+        } else {
+            if blocks.list.is_some() || !blocks.quote.is_empty() {
+                blocks.flush();
+            }
+            blocks.paragraph.push(inline(line.trim(), members));
+        }
+    }
+
+    // an unclosed code block runs to the end
+    if let Some(lines) = code {
+        blocks.html.push_str(&code_block(&lines));
+    }
+    blocks.flush();
+
+    blocks.html
+}
+
+// a span between `delim`s, like `**bold**`, with the length it takes
+fn delimited(text: &str, delim: &str, tag: &str, members: &[Member]) -> Option<(usize, String)> {
+    if !text.starts_with(delim) {
+        return None;
+    }
+
+    let rest = &text[delim.len()..];
+    match rest.find(delim) {
+        Some(end) if end > 0 && !rest.starts_with(char::is_whitespace) => {
+            let html = format!("<{}>{}</{}>", tag, inline(&rest[..end], members), tag);
+            Some((delim.len() * 2 + end, html))
+        }
+        _ => None,
+    }
+}
+
+fn code_span(text: &str) -> Option<(usize, String)> {
+    if !text.starts_with('`') {
+        return None;
+    }
+
+    text[1..].find('`').map(|end| (end + 2, format!("<code>{}</code>", escape(&text[1..end + 1]))))
+}
+
+// `[text](url)` split into text and url, with the length it takes
+fn bracketed(text: &str) -> Option<(usize, &str, &str)> {
+    if !text.starts_with('[') {
+        return None;
+    }
+
+    let middle = text.find("](")?;
+    let end = match text[middle..].find(')') {
+        Some(end) => middle + end,
+        None => return None,
+    };
+
+    let url = &text[middle + 2..end];
+    if url.is_empty() || url.contains(char::is_whitespace) {
+        return None;
+    }
+
+    Some((end + 1, &text[1..middle], url))
+}
+
+fn link(text: &str, members: &[Member]) -> Option<(usize, String)> {
+    bracketed(text).map(|(len, text, url)| {
+        (len, format!("<a href=\"{}\">{}</a>", escape(url), inline(text, members)))
+    })
+}
+
+// `![alt](src)`
+fn image(text: &str) -> Option<(usize, String)> {
+    if !text.starts_with('!') {
+        return None;
+    }
+
+    bracketed(&text[1..]).map(|(len, alt, src)| {
+        (len + 1, format!("<img src=\"{}\" alt=\"{}\">", escape(src), escape(alt)))
+    })
+}
+
+fn autolink(text: &str) -> Option<(usize, String)> {
+    if !text.starts_with("http://") && !text.starts_with("https://") {
+        return None;
+    }
+
+    let end = text.find(|c: char| c.is_whitespace() || c == '<').unwrap_or(text.len());
+    // punctuation after a url belongs to the sentence
+    let url = text[..end].trim_end_matches(|c| ".,;:!?)".contains(c));
+    let html = format!("<a href=\"{}\">{}</a>", escape(url), escape(url));
+
+    Some((url.len(), html))
+}
+
+// `@nickname`, the longest nickname wins as they may contain spaces
+fn mention(text: &str, members: &[Member]) -> Option<(usize, String)> {
+    if !text.starts_with('@') {
+        return None;
+    }
+
+    members.iter()
+        .filter(|x| !x.nickname.is_empty() && text[1..].starts_with(&x.nickname))
+        .max_by_key(|x| x.nickname.len())
+        .map(|x| (x.nickname.len() + 1, x.mention_html()))
+}
+
+fn inline(text: &str, members: &[Member]) -> String {
+    let mut html = String::new();
+    let mut i = 0;
+
+    while i < text.len() {
+        let rest = &text[i..];
+        let c = rest.chars().next().unwrap();
+        let word_start = text[..i].chars().next_back().is_none_or(|x| !x.is_alphanumeric());
+
+        // `\*` is a literal star
+        if c == '\\' {
+            if let Some(next) = rest[1..].chars().next().filter(|x| x.is_ascii_punctuation()) {
+                html.push_str(&escape(&next.to_string()));
+                i += 1 + next.len_utf8();
+                continue;
+            }
+        }
+
+        let token = code_span(rest)
+            .or_else(|| delimited(rest, "**", "strong", members))
+            .or_else(|| delimited(rest, "*", "em", members))
+            .or_else(|| if word_start { delimited(rest, "_", "em", members) } else { None })
+            .or_else(|| image(rest))
+            .or_else(|| link(rest, members))
+            .or_else(|| if word_start { autolink(rest) } else { None })
+            .or_else(|| if word_start { mention(rest, members) } else { None });
+
+        match token {
+            Some((len, token)) => {
+                html.push_str(&token);
+                i += len;
+            }
+            None => {
+                html.push_str(&escape(&c.to_string()));
+                i += c.len_utf8();
+            }
+        }
+    }
+
+    html
+}
+
+/// Readable Markdown of tower.im html, like the content of a weekly report.
+pub fn to_markdown(html: &str) -> String {
+    let fragment = Html::parse_fragment(html);
+    tidy(&children(fragment.root_element()))
+}
+
+fn block(text: &str) -> String {
+    format!("\n\n{}\n\n", text)
+}
+
+// whitespace in html is one space, and none at the start of a line
+fn push_text(out: &mut String, text: &str) {
+    for word in text.split(char::is_whitespace) {
+        if word.is_empty() {
+            if !out.is_empty() && !out.ends_with(char::is_whitespace) {
+                out.push(' ');
+            }
+        } else {
+            out.push_str(word);
+            out.push(' ');
+        }
+    }
+    // only the whitespace really in `text` is kept
+    if !text.ends_with(char::is_whitespace) && out.ends_with(' ') {
+        out.pop();
+    }
+}
+
+fn children(element: ElementRef) -> String {
+    let mut out = String::new();
+
+    for child in element.children() {
+        if let Some(element) = ElementRef::wrap(child) {
+            out.push_str(&render(element));
+        } else if let Node::Text(ref text) = *child.value() {
+            push_text(&mut out, text);
+        }
+    }
+
+    out
+}
+
+fn render(element: ElementRef) -> String {
+    let name = element.value().name();
+    let inner = || tidy(&children(element));
+
+    match name {
+        "br" => "\n".to_owned(),
+        "p" | "div" => block(&inner()),
+        "h1" | "h2" | "h3" | "h4" | "h5" | "h6" => {
+            let level = name[1..].parse().unwrap_or(1);
+            block(&format!("{} {}", "#".repeat(level), inner()))
+        }
+        "strong" | "b" => wrap(&inner(), "**"),
+        "em" | "i" => wrap(&inner(), "*"),
+        "code" => wrap(&element.text().collect::<String>(), "`"),
+        // whitespace matters in code blocks
+        "pre" => {
+            let code = element.text().collect::<String>();
+            block(&format!("```\n{}\n```", code.trim_end()))
+        }
+        "blockquote" => {
+            let quote: Vec<String> = inner().lines().map(|x| format!("> {}", x)).collect();
+            block(&quote.join("\n"))
+        }
+        "ul" | "ol" => {
+            let items: Vec<String> = element.children()
+                .filter_map(ElementRef::wrap)
+                .filter(|x| x.value().name() == "li")
+                .enumerate()
+                .map(|(i, li)| {
+                    let marker = match name {
+                        "ol" => format!("{}. ", i + 1),
+                        _ => "- ".to_owned(),
+                    };
+                    let indent = format!("\n{}", " ".repeat(marker.len()));
+                    let text = tidy(&children(li));
+                    format!("{}{}", marker, text.lines().collect::<Vec<_>>().join(&indent))
+                })
+                .collect();
+            block(&items.join("\n"))
+        }
+        "a" => {
+            let text = inner();
+            match element.value().attr("href") {
+                // mentions read `@nickname` already
+                _ if element.value().attr("data-mention").is_some() => text,
+                Some(href) if href != text && !text.is_empty() => format!("[{}]({})", text, href),
+                _ => text,
+            }
+        }
+        "img" => {
+            let alt = element.value().attr("alt").unwrap_or("");
+            format!("![{}]({})", alt, element.value().attr("src").unwrap_or(""))
+        }
+        _ => children(element),
+    }
+}
+
+fn wrap(text: &str, delim: &str) -> String {
+    if text.trim().is_empty() {
+        return text.to_owned();
+    }
+
+    format!("{}{}{}", delim, text.trim(), delim)
+}
+
+// trailing spaces and runs of blank lines removed
+fn tidy(text: &str) -> String {
+    let mut lines: Vec<&str> = vec![];
+
+    for line in text.lines().map(|x| x.trim_end()) {
+        if line.is_empty() && lines.last().is_none_or(|x| x.is_empty()) {
+            continue;
+        }
+        lines.push(line);
+    }
+
+    lines.join("\n").trim().to_owned()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn member(nickname: &str) -> Member {
+        Member {
+            guid: "abc".to_owned(),
+            nickname: nickname.to_owned(),
+        }
+    }
+
+    #[test]
+    fn images() {
+        let html = to_html("see ![shot](/a.png)", &[]);
+        assert_eq!(html, "<p>see <img src=\"/a.png\" alt=\"shot\"></p>");
+        assert_eq!(to_markdown(&html), "see ![shot](/a.png)");
+    }
+
+    #[test]
+    fn lists_and_mentions() {
+        let members = [member("sbw")];
+        let html = to_html("- fixed **login**\n- reviewed @sbw", &members);
+        assert_eq!(html,
+                   format!("<ul><li>fixed <strong>login</strong></li><li>reviewed {}</li></ul>",
+                           members[0].mention_html()));
+        assert_eq!(to_markdown(&html), "- fixed **login**\n- reviewed @sbw");
+    }
+
+    #[test]
+    fn escapes_text() {
+        assert_eq!(to_html("a < b & \"c\"", &[]), "<p>a &lt; b &amp; &quot;c&quot;</p>");
+        assert_eq!(to_markdown("<p>a &lt; b<br>c</p>"), "a < b\nc");
+    }
+}
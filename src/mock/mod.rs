@@ -0,0 +1,309 @@
+
+//! A local stand-in of tower.im serving fixture pages, for tests without network.
+//!
+//! Pages under `/teams/` and `/members/` need a `remember_token` cookie, other
+//! requests are redirected to the sign in page like tower.im does. Sign in with
+//! any email and password gets the mock session cookies.
+
+use std::convert::Infallible;
+use std::io::{self, Write};
+use std::net::{SocketAddr, TcpListener, ToSocketAddrs};
+use std::sync::{Arc, Mutex};
+use std::thread::{self, JoinHandle};
+
+use hyper::header::{CONTENT_TYPE, LOCATION, SET_COOKIE};
+use hyper::service::{make_service_fn, service_fn};
+use hyper::{Body, Request, Response, Server, StatusCode};
+
+use regex::Regex;
+
+use tokio::runtime;
+use tokio::sync::oneshot;
+
+pub const TEAM_GUID: &str = "mockteam0001";
+pub const MEMBER_GUID: &str = "mockmember0001";
+pub const CONN_GUID: &str = "mockconnguid0001";
+pub const CSRF_TOKEN: &str = "mock-csrf-token";
+pub const REMEMBER_TOKEN: &str = "mock-remember-token";
+
+const SIGN_IN: &str = include_str!("fixtures/sign_in.html");
+const MEMBERS: &str = include_str!("fixtures/members.html");
+const TEAMS: &str = include_str!("fixtures/teams.html");
+const WEEKLY_REPORTS: &str = include_str!("fixtures/weekly_reports.html");
+const WEEKLY_EDIT: &str = include_str!("fixtures/weekly_edit.json");
+const WEEKLY_SUBMIT: &str = include_str!("fixtures/weekly_submit.json");
+const CALENDAR_EVENTS: &str = include_str!("fixtures/calendar_events.json");
+const CALENDAR_EVENT_CREATE: &str = include_str!("fixtures/calendar_event_create.json");
+const COMMENT_CREATE: &str = include_str!("fixtures/comment_create.json");
+
+/// A request received by the mock server.
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct RecordedRequest {
+    pub method: String,
+    /// path with query string
+    pub path: String,
+    pub headers: Vec<(String, String)>,
+    pub body: String,
+}
+
+impl RecordedRequest {
+    pub fn header<T: AsRef<str>>(&self, name: T) -> Option<&str> {
+        let name = name.as_ref().to_lowercase();
+        self.headers
+            .iter()
+            .find(|&(k, _)| k.to_lowercase() == name)
+            .map(|(_, v)| v.as_str())
+    }
+}
+
+/// A response of the mock server.
+pub struct MockResponse {
+    pub status: StatusCode,
+    pub content_type: &'static str,
+    pub set_cookies: Vec<String>,
+    pub location: Option<String>,
+    pub body: String,
+}
+
+impl MockResponse {
+    fn html<T: AsRef<str>>(body: T) -> MockResponse {
+        MockResponse {
+            status: StatusCode::OK,
+            content_type: "text/html; charset=utf-8",
+            set_cookies: vec![],
+            location: None,
+            body: body.as_ref().to_owned(),
+        }
+    }
+
+    fn json<T: AsRef<str>>(body: T) -> MockResponse {
+        MockResponse {
+            content_type: "application/json; charset=utf-8",
+            ..MockResponse::html(body)
+        }
+    }
+
+    fn redirect<T: AsRef<str>>(location: T) -> MockResponse {
+        MockResponse {
+            status: StatusCode::FOUND,
+            location: Some(location.as_ref().to_owned()),
+            ..MockResponse::html("")
+        }
+    }
+
+    fn not_found() -> MockResponse {
+        MockResponse {
+            status: StatusCode::NOT_FOUND,
+            ..MockResponse::html("<h1>404</h1>")
+        }
+    }
+}
+
+/// Answer a request the way tower.im would, with fixture content.
+pub fn respond(request: &RecordedRequest) -> MockResponse {
+    let path = request.path.split('?').next().unwrap_or("");
+    let signed_in = request.header("Cookie")
+        .is_some_and(|x| x.contains(&format!("remember_token={}", REMEMBER_TOKEN)));
+
+    if path == "/users/sign_in" {
+        return match request.method.as_str() {
+            "POST" => {
+                let mut response = MockResponse::redirect(format!("/teams/{}/", TEAM_GUID));
+                response.set_cookies = vec![format!("remember_token={}; path=/; HttpOnly",
+                                                    REMEMBER_TOKEN),
+                                            format!("remember_team_guid={}; path=/", TEAM_GUID),
+                                            "_tower2_session=mock-session; path=/; HttpOnly"
+                                                .to_owned()];
+                response
+            }
+            _ => MockResponse::html(SIGN_IN),
+        };
+    }
+
+    if !signed_in {
+        return MockResponse::redirect("/users/sign_in");
+    }
+
+    let team_re = Regex::new(r"^/teams/(\w+)/(members/|calendar_events/)?$").unwrap();
+    let member_re =
+        Regex::new(r"^/members/(\w+)/(calendar_events/|weekly_reports/(\d+-\d+)?/?(edit)?)$")
+            .unwrap();
+    let comment_re = Regex::new(r"^/calendar_events/(\w+)/comments$").unwrap();
+
+    let mut response = match (request.method.as_str(), path) {
+        ("GET", "/teams/") => MockResponse::html(TEAMS),
+        ("GET", p) if team_re.is_match(p) => {
+            let caps = team_re.captures(p).unwrap();
+            match caps.get(2).map(|x| x.as_str()) {
+                Some("calendar_events/") => MockResponse::json(CALENDAR_EVENTS),
+                _ => MockResponse::html(MEMBERS),
+            }
+        }
+        ("POST", p) if team_re.is_match(p) && p.ends_with("/calendar_events/") => {
+            MockResponse::json(CALENDAR_EVENT_CREATE)
+        }
+        ("GET", p) if member_re.is_match(p) => {
+            let caps = member_re.captures(p).unwrap();
+            if caps.get(4).is_some() {
+                MockResponse::json(WEEKLY_EDIT)
+            } else if p.ends_with("/calendar_events/") {
+                MockResponse::json(CALENDAR_EVENTS)
+            } else {
+                MockResponse::html(WEEKLY_REPORTS)
+            }
+        }
+        ("POST", p) if member_re.is_match(p) && p.contains("/weekly_reports/") => {
+            MockResponse::json(WEEKLY_SUBMIT)
+        }
+        ("POST", p) if comment_re.is_match(p) => MockResponse::json(COMMENT_CREATE),
+        _ => MockResponse::not_found(),
+    };
+
+    if path.ends_with("/members/") {
+        response.set_cookies.push("_tower2_session=mock-session; path=/; HttpOnly".to_owned());
+    }
+
+    response
+}
+
+struct MockHandler {
+    requests: Mutex<Vec<RecordedRequest>>,
+    log: Option<Mutex<Box<dyn Write + Send>>>,
+}
+
+impl MockHandler {
+    async fn handle(&self, req: Request<Body>) -> Response<Body> {
+        let (parts, body) = req.into_parts();
+        let path = match parts.uri.path_and_query() {
+            Some(path) => path.as_str().to_owned(),
+            None => parts.uri.to_string(),
+        };
+        let headers = parts.headers
+            .iter()
+            .map(|(k, v)| {
+                (k.as_str().to_owned(), String::from_utf8_lossy(v.as_bytes()).into_owned())
+            })
+            .collect();
+        let body = match hyper::body::to_bytes(body).await {
+            Ok(body) => String::from_utf8_lossy(&body).into_owned(),
+            Err(_) => String::new(),
+        };
+
+        let recorded = RecordedRequest {
+            method: parts.method.to_string(),
+            path,
+            headers,
+            body,
+        };
+
+        if let Some(ref log) = self.log {
+            if let Ok(line) = serde_json::to_string(&recorded) {
+                let mut log = log.lock().unwrap();
+                let _ = writeln!(log, "{}", line);
+                let _ = log.flush();
+            }
+        }
+
+        let response = respond(&recorded);
+        self.requests.lock().unwrap().push(recorded);
+
+        let mut builder = Response::builder()
+            .status(response.status)
+            .header(CONTENT_TYPE, response.content_type);
+        if let Some(location) = response.location {
+            builder = builder.header(LOCATION, location);
+        }
+        for cookie in response.set_cookies {
+            builder = builder.header(SET_COOKIE, cookie);
+        }
+
+        builder.body(Body::from(response.body)).unwrap()
+    }
+}
+
+/// The mock server running in a background thread.
+pub struct MockServer {
+    addr: SocketAddr,
+    handler: Arc<MockHandler>,
+    // stops the server when sent or dropped
+    shutdown: oneshot::Sender<()>,
+    thread: JoinHandle<()>,
+}
+
+impl MockServer {
+    /// Listen on `addr`, use port 0 to pick a free port. Received requests are
+    /// written to `log` as json lines if given.
+    pub fn start<A: ToSocketAddrs>(addr: A,
+                                   log: Option<Box<dyn Write + Send>>)
+                                   -> io::Result<MockServer> {
+        let listener = TcpListener::bind(addr)?;
+        let addr = listener.local_addr()?;
+        listener.set_nonblocking(true)?;
+
+        let handler = Arc::new(MockHandler {
+            requests: Mutex::new(vec![]),
+            log: log.map(Mutex::new),
+        });
+
+        let runtime = runtime::Builder::new_current_thread().enable_all().build()?;
+        let builder = {
+            let _guard = runtime.enter();
+            Server::from_tcp(listener).map_err(io::Error::other)?
+        };
+
+        let service_handler = handler.clone();
+        let make_service = make_service_fn(move |_| {
+            let handler = service_handler.clone();
+            async move {
+                Ok::<_, Infallible>(service_fn(move |req| {
+                    let handler = handler.clone();
+                    async move { Ok::<_, Infallible>(handler.handle(req).await) }
+                }))
+            }
+        });
+
+        let (shutdown, stopped) = oneshot::channel::<()>();
+        let server = builder.serve(make_service).with_graceful_shutdown(async move {
+            let _ = stopped.await;
+        });
+        let thread = thread::spawn(move || {
+            if let Err(e) = runtime.block_on(server) {
+                warn!("mock server failed: {}", e);
+            }
+        });
+        info!("mock server listening on {}", addr);
+
+        Ok(MockServer {
+            addr,
+            handler,
+            shutdown,
+            thread,
+        })
+    }
+
+    pub fn addr(&self) -> SocketAddr {
+        self.addr
+    }
+
+    /// Base url to give `TowerClient`.
+    pub fn url(&self) -> String {
+        format!("http://{}", self.addr)
+    }
+
+    /// Requests received so far.
+    pub fn requests(&self) -> Vec<RecordedRequest> {
+        self.handler.requests.lock().unwrap().clone()
+    }
+
+    /// Block until the server is closed.
+    pub fn join(self) {
+        // keep `shutdown` alive, dropping it stops the server
+        let MockServer { shutdown: _shutdown, thread, .. } = self;
+        let _ = thread.join();
+    }
+
+    pub fn close(self) {
+        let _ = self.shutdown.send(());
+        let _ = self.thread.join();
+    }
+}
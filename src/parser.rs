@@ -0,0 +1,316 @@
+
+//! Parse tower.im pages with a real html parser and css selectors.
+//!
+//! Every function fails with `TowerError::Parse` naming the missing element, or
+//! `TowerError::CsrfMissing`, so a changed page shows up as a clear error
+//! instead of empty results.
+
+use scraper::{ElementRef, Html, Selector};
+
+use crate::error::{Result, TowerError};
+use crate::team::Team;
+use crate::types::{Member, WeeklyField, WeeklyReportSection};
+
+/// Session info found in the members page.
+#[derive(Clone, Debug)]
+pub struct MembersPage {
+    pub csrf_token: String,
+    pub conn_guid: String,
+    pub uid: String,
+    pub members: Vec<Member>,
+}
+
+fn selector(s: &str) -> Selector {
+    Selector::parse(s).unwrap()
+}
+
+fn missing(what: &str, page: &str) -> TowerError {
+    TowerError::Parse(format!("`{}` in {}", what, page))
+}
+
+fn text(element: ElementRef) -> String {
+    element.text().collect::<String>().trim().to_owned()
+}
+
+// text nodes right before and after `element`, without text of other elements
+fn text_around(element: ElementRef) -> String {
+    let mut nodes: Vec<_> = element.prev_siblings().take_while(|x| x.value().is_text()).collect();
+    nodes.reverse();
+    nodes.extend(element.next_siblings().take_while(|x| x.value().is_text()));
+
+    nodes.iter()
+        .filter_map(|x| x.value().as_text())
+        .map(|x| &**x)
+        .collect::<String>()
+        .trim()
+        .to_owned()
+}
+
+// value of attribute `attr` of the first element matching `s`
+fn attr_of(html: &Html, s: &str, attr: &str, page: &str) -> Result<String> {
+    html.select(&selector(s))
+        .next()
+        .and_then(|x| x.value().attr(attr))
+        .map(|x| x.to_owned())
+        .ok_or_else(|| missing(&format!("{}[{}]", s, attr), page))
+}
+
+// `/teams/abc/` -> `abc`
+fn guid_of<'a>(href: &'a str, prefix: &str) -> Option<&'a str> {
+    if !href.starts_with(prefix) {
+        return None;
+    }
+
+    let guid = href[prefix.len()..].split('/').next().unwrap_or("");
+    if !guid.is_empty() && guid.chars().all(|c| c.is_alphanumeric() || c == '_') {
+        Some(guid)
+    } else {
+        None
+    }
+}
+
+/// Csrf token in the `<meta>` of any page.
+pub fn csrf_token<T: AsRef<str>>(content: T, page: &str) -> Result<String> {
+    let html = Html::parse_document(content.as_ref());
+    csrf_meta(&html, page)
+}
+
+fn csrf_meta(html: &Html, page: &str) -> Result<String> {
+    attr_of(html, r#"meta[name="csrf-token"]"#, "content", page)
+        .map_err(|_| TowerError::CsrfMissing(page.to_owned()))
+}
+
+/// Guid of the first team linked in the page.
+pub fn team_guid<T: AsRef<str>>(content: T) -> Result<String> {
+    let html = Html::parse_document(content.as_ref());
+
+    html.select(&selector(r#"a[href^="/teams/"]"#))
+        .filter_map(|x| x.value().attr("href"))
+        .filter_map(|x| guid_of(x, "/teams/"))
+        .next()
+        .map(|x| x.to_owned())
+        .ok_or_else(|| missing(r#"a[href^="/teams/"]"#, "team page"))
+}
+
+/// Csrf token, guids and the member list in the members page.
+pub fn members_page<T: AsRef<str>>(content: T) -> Result<MembersPage> {
+    let html = Html::parse_document(content.as_ref());
+    let page = "members page";
+
+    Ok(MembersPage {
+        csrf_token: csrf_meta(&html, page)?,
+        conn_guid: attr_of(&html, "#conn-guid", "value", page)?,
+        uid: attr_of(&html, "#member-guid", "value", page)?,
+        members: members(&html),
+    })
+}
+
+fn members(html: &Html) -> Vec<Member> {
+    let nickname = selector(".member-nickname");
+    let mut members: Vec<Member> = vec![];
+
+    for link in html.select(&selector(r#"a[href^="/members/"]"#)) {
+        let guid = match link.value().attr("href").and_then(|x| guid_of(x, "/members/")) {
+            Some(guid) => guid,
+            None => continue,
+        };
+
+        // nickname is inside the link, or next to it
+        let name = link.select(&nickname).next().or_else(|| {
+            link.parent()
+                .and_then(ElementRef::wrap)
+                .and_then(|x| x.select(&nickname).next())
+        });
+        let name = match name {
+            Some(name) => text(name),
+            None => continue,
+        };
+
+        if !members.iter().any(|x| x.guid == guid) {
+            members.push(Member {
+                guid: guid.to_owned(),
+                nickname: name,
+            });
+        }
+    }
+
+    members
+}
+
+/// Teams listed in the team switching page.
+pub fn teams<T: AsRef<str>>(content: T) -> Result<Vec<Team>> {
+    let html = Html::parse_document(content.as_ref());
+    let mut teams: Vec<Team> = vec![];
+
+    for link in html.select(&selector(r#"a[href^="/teams/"]"#)) {
+        let guid = match link.value().attr("href").and_then(|x| guid_of(x, "/teams/")) {
+            Some(guid) => guid,
+            None => continue,
+        };
+        let name = text(link);
+
+        if !name.is_empty() && !teams.iter().any(|x| x.guid == guid) {
+            teams.push(Team {
+                guid: guid.to_owned(),
+                name,
+            });
+        }
+    }
+
+    if teams.is_empty() {
+        return Err(missing(r#"a[href^="/teams/"]"#, "teams page"));
+    }
+
+    Ok(teams)
+}
+
+/// Sections of a weekly report page, each `<dt>` title with its `<dd>` content.
+pub fn weekly_report_sections<T: AsRef<str>>(content: T) -> Result<Vec<WeeklyReportSection>> {
+    let html = Html::parse_document(content.as_ref());
+    let mut sections = vec![];
+
+    for dt in html.select(&selector("dt")) {
+        let title = text(dt);
+        let dd = dt.next_siblings().filter_map(ElementRef::wrap).next();
+
+        match dd {
+            Some(dd) if dd.value().name() == "dd" => {
+                sections.push(WeeklyReportSection {
+                    title,
+                    content: dd.inner_html().trim().to_owned(),
+                })
+            }
+            _ => return Err(missing(&format!("dd after title {}", title), "weekly report")),
+        }
+    }
+
+    Ok(sections)
+}
+
+/// Questions of the weekly report form, the `html` of the `/edit` response.
+pub fn weekly_fields<T: AsRef<str>>(content: T) -> Result<Vec<WeeklyField>> {
+    let html = Html::parse_fragment(content.as_ref());
+    let page = "weekly report form";
+    let mut fields = vec![];
+
+    for input in html.select(&selector("input[name]")) {
+        let name = input.value().attr("name").unwrap_or("");
+        let value = match input.value().attr("value") {
+            Some(value) => value,
+            None => return Err(missing(&format!("input[name={}][value]", name), page)),
+        };
+
+        // the question is the text around the input, not the answer or help
+        // text elsewhere in the same item
+        let title = text_around(input);
+        if title.is_empty() {
+            return Err(missing(&format!("title of question {}", value), page));
+        }
+
+        fields.push(WeeklyField {
+            name: name.to_owned(),
+            value: value.to_owned(),
+            title,
+        });
+    }
+
+    if fields.is_empty() {
+        return Err(missing("input[name]", page));
+    }
+
+    Ok(fields)
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::error::TowerError;
+
+    use super::*;
+
+    const MEMBERS: &str = include_str!("mock/fixtures/members.html");
+
+    fn parse_error<T>(result: Result<T>) -> String {
+        match result {
+            Err(TowerError::Parse(what)) => what,
+            Err(e) => panic!("{:?}", e),
+            Ok(_) => panic!("parsed"),
+        }
+    }
+
+    #[test]
+    fn members_page_fixture() {
+        let page = members_page(MEMBERS).unwrap();
+
+        assert_eq!(page.csrf_token, "mock-csrf-token");
+        assert_eq!(page.conn_guid, "mockconnguid0001");
+        assert_eq!(page.uid, "mockmember0001");
+        assert_eq!(page.members.len(), 3);
+        assert_eq!(page.members[2].nickname, "Alice & Bob");
+    }
+
+    #[test]
+    fn missing_csrf_meta() {
+        let page = MEMBERS.replace(r#"name="csrf-token""#, r#"name="other""#);
+
+        match members_page(page) {
+            Err(TowerError::CsrfMissing(page)) => assert_eq!(page, "members page"),
+            r => panic!("{:?}", r),
+        }
+    }
+
+    #[test]
+    fn missing_guids() {
+        let page = MEMBERS.replace(r#"id="conn-guid""#, "");
+        assert_eq!(parse_error(members_page(page)), "`#conn-guid[value]` in members page");
+
+        let page = MEMBERS.replace(r#"id="member-guid""#, "");
+        assert_eq!(parse_error(members_page(page)), "`#member-guid[value]` in members page");
+    }
+
+    #[test]
+    fn missing_dd() {
+        let page = "<dl><dt>本周完成的工作</dt><dd>done</dd><dt>下周计划</dt></dl>";
+        assert_eq!(parse_error(weekly_report_sections(page)),
+                   "`dd after title 下周计划` in weekly report");
+
+        let page = "<dl><dt>本周完成的工作</dt><dt>下周计划</dt><dd>plan</dd></dl>";
+        assert_eq!(parse_error(weekly_report_sections(page)),
+                   "`dd after title 本周完成的工作` in weekly report");
+    }
+
+    #[test]
+    fn weekly_fields_without_inputs() {
+        let form = r#"<form class="form-weekly-report"><div>本周完成的工作</div></form>"#;
+        assert_eq!(parse_error(weekly_fields(form)), "`input[name]` in weekly report form");
+    }
+
+    #[test]
+    fn weekly_field_title_next_to_input() {
+        let form = concat!(r#"<div class="form-item"><span class="help">写清楚</span>"#,
+                           r#" <input type="hidden" name="question_guid" value="q1"> 本周"#,
+                           r#"完成的工作 <textarea>上周的回答</textarea></div>"#);
+        let fields = weekly_fields(form).unwrap();
+
+        assert_eq!(fields.len(), 1);
+        assert_eq!(fields[0].value, "q1");
+        assert_eq!(fields[0].title, "本周完成的工作");
+
+        let form = r#"<div><input name="question_guid" value="q1"><b>本周完成的工作</b></div>"#;
+        assert_eq!(parse_error(weekly_fields(form)),
+                   "`title of question q1` in weekly report form");
+    }
+
+    #[test]
+    fn teams_page() {
+        let teams = teams(include_str!("mock/fixtures/teams.html")).unwrap();
+        let names: Vec<&str> = teams.iter().map(|x| x.name.as_str()).collect();
+        assert_eq!(names, vec!["Mock Team", "Another Team"]);
+        assert_eq!(teams[1].guid, "mockteam0002");
+    }
+
+    #[test]
+    fn unrecognisable_teams_page() {
+        let page = r#"<html><body><a href="/users/sign_in">登录</a></body></html>"#;
+        assert_eq!(parse_error(teams(page)), r#"`a[href^="/teams/"]` in teams page"#);
+    }
+}
@@ -0,0 +1,217 @@
+
+//! Weekly report answers from Markdown, YAML or JSON files, so reports made
+//! by scripts can be submitted without prompts.
+//!
+//! Sections are matched to form fields by title, and every field needs one.
+//! In Markdown a heading with the field title starts a section, other
+//! headings are part of the answer:
+//!
+//! ```text
+//! # Weekly report
+//!
+//! ## What did you do this week?
+//! Fixed the login page.
+//!
+//! ## What's next?
+//! Release 1.0.
+//! ```
+//!
+//! YAML and JSON files map titles to answers in Markdown, a section left
+//! empty or `null` is an empty answer:
+//!
+//! ```text
+//! {"What did you do this week?": "- Fixed the login page.", "What's next?": ""}
+//! ```
+
+use std::collections::BTreeMap;
+use std::fs::File;
+use std::io::Read;
+use std::path::Path;
+
+use regex::Regex;
+
+use crate::error::{Result, TowerError};
+use crate::types::WeeklyField;
+
+/// Markdown answers of `fields` in the report file at `path`, in field order.
+///
+/// The format is told by the extension, `.md`, `.yaml` or `.json`.
+pub fn load<P: AsRef<Path>>(path: P, fields: &[WeeklyField]) -> Result<Vec<String>> {
+    let path = path.as_ref();
+    let mut content = String::new();
+    File::open(path)?.read_to_string(&mut content)?;
+
+    let extension = path.extension()
+        .map(|x| x.to_string_lossy().to_lowercase())
+        .unwrap_or(String::new());
+    let answers = match extension.as_str() {
+        "md" | "markdown" => from_markdown(&content, fields),
+        "yaml" | "yml" => {
+            let sections: BTreeMap<String, Option<String>> = serde_yaml::from_str(&content)
+                .map_err(|e| TowerError::InvalidReport(e.to_string()))?;
+            from_sections(sections, fields)
+        }
+        "json" => {
+            let sections: BTreeMap<String, Option<String>> = serde_json::from_str(&content)
+                .map_err(|e| TowerError::InvalidReport(e.to_string()))?;
+            from_sections(sections, fields)
+        }
+        _ => {
+            return Err(TowerError::InvalidReport(format!("unknown format of {}, use .md, \
+                                                          .yaml or .json",
+                                                         path.display())))
+        }
+    };
+
+    answers.map_err(|e| match e {
+        TowerError::InvalidReport(msg) => {
+            TowerError::InvalidReport(format!("{}: {}", path.display(), msg))
+        }
+        e => e,
+    })
+}
+
+/// Answers in a Markdown report.
+pub fn from_markdown(content: &str, fields: &[WeeklyField]) -> Result<Vec<String>> {
+    let heading_re = Regex::new(r"^#{1,6}\s+(.*?)[\s#]*$").unwrap();
+    let mut sections: Vec<Option<Vec<&str>>> = vec![None; fields.len()];
+    let mut current = None;
+    let mut in_code = false;
+
+    for line in content.lines() {
+        if line.trim_start().starts_with("```") {
+            in_code = !in_code;
+        }
+
+        if !in_code {
+            let title = heading_re.captures(line).and_then(|x| x.get(1)).map(|x| x.as_str());
+            let field = title.and_then(|title| {
+                fields.iter()
+                    .enumerate()
+                    .position(|(i, x)| sections[i].is_none() && x.matches(title))
+            });
+            if let Some(i) = field {
+                sections[i] = Some(vec![]);
+                current = Some(i);
+                continue;
+            }
+        }
+
+        // text before the first section, like a document title, is dropped
+        if let Some(i) = current {
+            if let Some(ref mut lines) = sections[i] {
+                lines.push(line);
+            }
+        }
+    }
+
+    check_covered(&sections, fields)?;
+
+    Ok(sections.into_iter()
+        .map(|x| x.unwrap_or(vec![]).join("\n").trim().to_owned())
+        .collect())
+}
+
+/// Answers in a title to answer map, read from YAML or JSON.
+pub fn from_sections(sections: BTreeMap<String, Option<String>>,
+                     fields: &[WeeklyField])
+                     -> Result<Vec<String>> {
+    let mut answers: Vec<Option<String>> = vec![None; fields.len()];
+
+    for (title, answer) in sections {
+        let i = match fields.iter().position(|x| x.matches(&title)) {
+            Some(i) => i,
+            None => return Err(TowerError::InvalidReport(format!("unknown section `{}`", title))),
+        };
+        if answers[i].is_some() {
+            return Err(TowerError::InvalidReport(format!("section `{}` given twice", title)));
+        }
+        answers[i] = Some(answer.map(|x| x.trim().to_owned()).unwrap_or(String::new()));
+    }
+
+    check_covered(&answers, fields)?;
+
+    Ok(answers.into_iter().map(|x| x.unwrap_or(String::new())).collect())
+}
+
+fn check_covered<T>(sections: &[Option<T>], fields: &[WeeklyField]) -> Result<()> {
+    let missing: Vec<String> = fields.iter()
+        .zip(sections)
+        .filter(|&(_, section)| section.is_none())
+        .map(|(field, _)| format!("`{}`", field.title))
+        .collect();
+
+    if !missing.is_empty() {
+        return Err(TowerError::InvalidReport(format!("no section for {}", missing.join(", "))));
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::env::temp_dir;
+    use std::fs::{remove_file, File};
+    use std::io::Write;
+    use std::process;
+
+    use super::*;
+
+    fn fields() -> Vec<WeeklyField> {
+        ["本周工作", "下周计划"]
+            .iter()
+            .enumerate()
+            .map(|(i, title)| {
+                WeeklyField {
+                    name: format!("answers[{}]", i),
+                    value: i.to_string(),
+                    title: title.to_string(),
+                }
+            })
+            .collect()
+    }
+
+    fn load_str(extension: &str, content: &str) -> Result<Vec<String>> {
+        let path = temp_dir().join(format!("tower-report-{}.{}", process::id(), extension));
+        File::create(&path).unwrap().write_all(content.as_bytes()).unwrap();
+        let answers = load(&path, &fields());
+        remove_file(&path).unwrap();
+
+        answers
+    }
+
+    #[test]
+    fn yaml_null_is_empty() {
+        let answers = load_str("yaml", "本周工作: |\n  - 登录页\n下周计划:\n").unwrap();
+        assert_eq!(answers, vec!["- 登录页".to_owned(), String::new()]);
+    }
+
+    #[test]
+    fn json_null_is_empty() {
+        let answers = load_str("json", r#"{"本周工作": "done", "下周计划": null}"#).unwrap();
+        assert_eq!(answers, vec!["done".to_owned(), String::new()]);
+    }
+
+    #[test]
+    fn markdown_sections() {
+        let content = "# 周报\n\n## 本周工作\n- a\n\n```\n## 下周计划\n```\n## 下周计划\nb\n";
+        let answers = from_markdown(content, &fields()).unwrap();
+        assert_eq!(answers, vec!["- a\n\n```\n## 下周计划\n```".to_owned(), "b".to_owned()]);
+    }
+
+    #[test]
+    fn unknown_and_missing_sections() {
+        let mut sections = BTreeMap::new();
+        sections.insert("本周工作".to_owned(), None);
+        sections.insert("other".to_owned(), Some("x".to_owned()));
+        match from_sections(sections, &fields()) {
+            Err(TowerError::InvalidReport(msg)) => assert_eq!(msg, "unknown section `other`"),
+            r => panic!("{:?}", r),
+        }
+
+        match from_markdown("## 本周工作\na\n", &fields()) {
+            Err(TowerError::InvalidReport(msg)) => assert_eq!(msg, "no section for `下周计划`"),
+            r => panic!("{:?}", r),
+        }
+    }
+}
@@ -0,0 +1,112 @@
+
+use std::env::{home_dir, var_os};
+use std::fs::{remove_file, DirBuilder, File, OpenOptions, Permissions};
+use std::io::{self, Read, Write};
+use std::os::unix::fs::{DirBuilderExt, OpenOptionsExt, PermissionsExt};
+use std::path::PathBuf;
+
+use time::get_time;
+
+use crate::types::Member;
+
+/// A cached value together with the time it was fetched.
+#[derive(Serialize, Deserialize, Clone, Debug, Default)]
+pub struct Cached<T> {
+    pub value: T,
+    pub updated_at: i64,
+}
+
+impl<T> Cached<T> {
+    pub fn new(value: T) -> Cached<T> {
+        Cached {
+            value,
+            updated_at: get_time().sec,
+        }
+    }
+}
+
+/// Everything scraped from tower.im that is needed to send requests.
+#[derive(Serialize, Deserialize, Clone, Debug, Default)]
+#[serde(default)]
+pub struct Session {
+    /// site the session belongs to, like `https://tower.im`
+    pub base_url: String,
+    /// where the cookies were read from, like the cookie file of a browser
+    /// profile, empty if not known
+    pub source: String,
+    pub cookies: Cached<Vec<String>>,
+    pub csrf_token: Cached<String>,
+    pub conn_guid: Cached<String>,
+    pub uid: Cached<String>,
+    pub tid: Cached<String>,
+    pub members: Cached<Vec<Member>>,
+}
+
+impl Session {
+    /// `$XDG_CACHE_HOME/tower/session`, defaults to `~/.cache/tower/session`.
+    pub fn path() -> Option<PathBuf> {
+        let cache_dir = match var_os("XDG_CACHE_HOME") {
+            Some(ref dir) if !dir.is_empty() => PathBuf::from(dir),
+            _ => {
+                match home_dir() {
+                    Some(home) => home.join(".cache"),
+                    None => return None,
+                }
+            }
+        };
+
+        Some(cache_dir.join("tower").join("session"))
+    }
+
+    pub fn load() -> Option<Session> {
+        let path = Session::path()?;
+
+        let mut content = String::new();
+        match File::open(&path) {
+            Ok(mut file) => {
+                if file.read_to_string(&mut content).is_err() {
+                    return None;
+                }
+            }
+            Err(_) => return None,
+        }
+
+        match serde_json::from_str(&content) {
+            Ok(session) => Some(session),
+            Err(e) => {
+                warn!("decode session cache {} failed: {}", path.display(), e);
+                None
+            }
+        }
+    }
+
+    /// Write the session, only readable by current user.
+    pub fn save(&self) -> io::Result<()> {
+        let path = match Session::path() {
+            Some(path) => path,
+            None => return Err(io::Error::new(io::ErrorKind::NotFound, "no cache directory")),
+        };
+
+        if let Some(dir) = path.parent() {
+            DirBuilder::new().recursive(true).mode(0o700).create(dir)?;
+        }
+
+        let content = serde_json::to_string(self)
+            .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e.to_string()))?;
+
+        let mut file = OpenOptions::new().write(true)
+            .create(true)
+            .truncate(true)
+            .mode(0o600)
+            .open(&path)?;
+        // the file may be created by an older version with looser mode
+        file.set_permissions(Permissions::from_mode(0o600))?;
+        file.write_all(content.as_bytes())
+    }
+
+    pub fn remove() {
+        if let Some(path) = Session::path() {
+            let _ = remove_file(path);
+        }
+    }
+}
@@ -0,0 +1,15 @@
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct Team {
+    pub guid: String,
+    pub name: String,
+}
+
+impl Team {
+    /// Match against a `--team` argument, either team name or guid.
+    pub fn matches<T: AsRef<str>>(&self, name: T) -> bool {
+        let name = name.as_ref();
+        self.guid == name || self.name == name
+    }
+}
+
@@ -0,0 +1,471 @@
+
+use std::cell::RefCell;
+use std::fs::File;
+use std::io::Read;
+use std::path::{Path, PathBuf};
+use std::rc::Rc;
+use std::time::Duration;
+
+use async_trait::async_trait;
+
+use reqwest::header::{HeaderMap, RETRY_AFTER};
+use reqwest::redirect;
+use reqwest::{Certificate, Client, Method, StatusCode};
+
+use tokio::time::sleep;
+
+use url::Url;
+
+use crate::error::{Result, TowerError};
+use crate::proxy::Proxy;
+
+#[derive(Clone, Debug)]
+pub struct Request {
+    pub method: Method,
+    pub url: String,
+    pub headers: HeaderMap,
+    pub body: Option<String>,
+    /// follow redirects, otherwise the redirect response is returned
+    pub follow_redirects: bool,
+}
+
+impl Request {
+    pub fn new<T: AsRef<str>>(method: Method, url: T, headers: HeaderMap) -> Request {
+        Request {
+            method,
+            url: url.as_ref().to_owned(),
+            headers,
+            body: None,
+            follow_redirects: true,
+        }
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct Response {
+    pub status: StatusCode,
+    /// url of the final response after redirects
+    pub url: String,
+    pub headers: HeaderMap,
+    pub body: String,
+}
+
+impl Response {
+    pub fn new<T: AsRef<str>, B: AsRef<str>>(status: StatusCode, url: T, body: B) -> Response {
+        Response {
+            status,
+            url: url.as_ref().to_owned(),
+            headers: HeaderMap::new(),
+            body: body.as_ref().to_owned(),
+        }
+    }
+
+    /// Path of the final url, like `/users/sign_in`.
+    pub fn path(&self) -> String {
+        match Url::parse(&self.url) {
+            Ok(url) => url.path().to_owned(),
+            Err(_) => self.url.clone(),
+        }
+    }
+}
+
+/// How requests are sent, so `TowerClient` can be used without network.
+///
+/// Futures are not `Send`, requests run concurrently on one thread.
+#[async_trait(?Send)]
+pub trait Transport {
+    async fn send(&self, request: &Request) -> Result<Response>;
+}
+
+// so a transport can be inspected after handing it to `TowerClient`
+#[async_trait(?Send)]
+impl<T: Transport + ?Sized> Transport for Rc<T> {
+    async fn send(&self, request: &Request) -> Result<Response> {
+        (**self).send(request).await
+    }
+}
+
+/// Connect and read timeouts of `HttpTransport`.
+#[derive(Clone, Copy, Debug)]
+pub struct Timeouts {
+    pub connect: Duration,
+    /// the whole request once connected, including the response body
+    pub read: Duration,
+}
+
+impl Default for Timeouts {
+    fn default() -> Timeouts {
+        Timeouts {
+            connect: Duration::from_secs(10),
+            read: Duration::from_secs(30),
+        }
+    }
+}
+
+/// Network settings of `HttpTransport`.
+#[derive(Clone, Debug, Default)]
+pub struct Options {
+    pub timeouts: Timeouts,
+    pub proxy: Option<Proxy>,
+    /// extra root certificates, pem or der
+    pub ca_file: Option<PathBuf>,
+}
+
+/// Send requests to the network with reqwest, over rustls.
+///
+/// Cookies are not kept here, `TowerClient` sends them itself.
+pub struct HttpTransport {
+    client: Client,
+    // used when redirects are not followed
+    no_redirect_client: Client,
+}
+
+impl HttpTransport {
+    pub fn new() -> Result<HttpTransport> {
+        HttpTransport::with_options(&Options::default())
+    }
+
+    pub fn with_options(options: &Options) -> Result<HttpTransport> {
+        Ok(HttpTransport {
+            client: HttpTransport::client(options, redirect::Policy::default())?,
+            no_redirect_client: HttpTransport::client(options, redirect::Policy::none())?,
+        })
+    }
+
+    fn client(options: &Options, policy: redirect::Policy) -> Result<Client> {
+        // proxies from the environment are picked by `Proxy::from_env` already
+        let mut builder = Client::builder()
+            .use_rustls_tls()
+            .redirect(policy)
+            .connect_timeout(options.timeouts.connect)
+            .timeout(options.timeouts.read)
+            .no_proxy();
+
+        if let Some(ref proxy) = options.proxy {
+            builder = builder.proxy(reqwest::Proxy::all(proxy.url())?);
+        }
+        if let Some(ref ca_file) = options.ca_file {
+            for cert in read_certificates(ca_file)? {
+                builder = builder.add_root_certificate(cert);
+            }
+        }
+
+        Ok(builder.build()?)
+    }
+}
+
+// a pem bundle or a single der certificate
+fn read_certificates(path: &Path) -> Result<Vec<Certificate>> {
+    let mut content = vec![];
+    File::open(path)?.read_to_end(&mut content)?;
+
+    let begin = "-----BEGIN CERTIFICATE-----";
+    let text = String::from_utf8_lossy(&content).into_owned();
+    let certs = if text.contains(begin) {
+        let mut certs = vec![];
+        for block in text.split(begin).skip(1) {
+            certs.push(Certificate::from_pem(format!("{}{}", begin, block).as_bytes())?);
+        }
+        certs
+    } else {
+        vec![Certificate::from_der(&content)?]
+    };
+
+    info!("load {} certificates from {}", certs.len(), path.display());
+    Ok(certs)
+}
+
+#[async_trait(?Send)]
+impl Transport for HttpTransport {
+    async fn send(&self, request: &Request) -> Result<Response> {
+        let client = if request.follow_redirects {
+            &self.client
+        } else {
+            &self.no_redirect_client
+        };
+
+        let mut builder = client.request(request.method.clone(), request.url.as_str())
+            .headers(request.headers.clone());
+        if let Some(ref body) = request.body {
+            builder = builder.body(body.clone());
+        }
+
+        let response = builder.send().await?;
+        let status = response.status();
+        let url = response.url().to_string();
+        let headers = response.headers().clone();
+        let body = response.text().await?;
+
+        Ok(Response {
+            status,
+            url,
+            headers,
+            body,
+        })
+    }
+}
+
+/// How failed requests are retried.
+#[derive(Clone, Copy, Debug)]
+pub struct RetryPolicy {
+    /// retries after the first attempt
+    pub retries: u32,
+    /// delay before the first retry, doubled every time
+    pub backoff: Duration,
+}
+
+impl Default for RetryPolicy {
+    fn default() -> RetryPolicy {
+        RetryPolicy {
+            retries: 3,
+            backoff: Duration::from_millis(500),
+        }
+    }
+}
+
+impl RetryPolicy {
+    /// Delay before retry `attempt`, starts from 0.
+    pub fn delay(&self, attempt: u32) -> Duration {
+        self.backoff * 2u32.pow(attempt.min(10))
+    }
+}
+
+/// Network errors and gateway errors, which may go away by trying again.
+pub fn is_transient(error: &TowerError) -> bool {
+    match *error {
+        TowerError::Http(_) |
+        TowerError::Io(_) => true,
+        TowerError::Status(status, _) => is_transient_status(status),
+        _ => false,
+    }
+}
+
+fn is_transient_status(status: StatusCode) -> bool {
+    status == StatusCode::BAD_GATEWAY || status == StatusCode::SERVICE_UNAVAILABLE ||
+    status == StatusCode::GATEWAY_TIMEOUT
+}
+
+// seconds of `Retry-After`, the http date form is not used by tower.im
+fn retry_after(response: &Response) -> Option<Duration> {
+    response.headers
+        .get(RETRY_AFTER)
+        .and_then(|x| x.to_str().ok())
+        .and_then(|x| x.trim().parse().ok())
+        .map(|x: u64| Duration::from_secs(x.min(60)))
+}
+
+/// Retry requests of another transport with exponential backoff.
+///
+/// Only GETs are retried on network errors and `502`, `503` or `504`, as a
+/// POST may have been processed anyway. Every request is retried on `429 Too
+/// Many Requests`, after the `Retry-After` delay if the server gives one.
+pub struct RetryTransport<T: Transport> {
+    inner: T,
+    policy: RetryPolicy,
+}
+
+impl<T: Transport> RetryTransport<T> {
+    pub fn new(inner: T, policy: RetryPolicy) -> RetryTransport<T> {
+        RetryTransport {
+            inner,
+            policy,
+        }
+    }
+}
+
+#[async_trait(?Send)]
+impl<T: Transport> Transport for RetryTransport<T> {
+    async fn send(&self, request: &Request) -> Result<Response> {
+        let idempotent = request.method == Method::GET;
+        let mut attempt = 0;
+
+        loop {
+            let result = self.inner.send(request).await;
+            if attempt >= self.policy.retries {
+                return result;
+            }
+
+            let delay = match result {
+                Ok(ref response) if response.status == StatusCode::TOO_MANY_REQUESTS => {
+                    retry_after(response).unwrap_or(self.policy.delay(attempt))
+                }
+                Ok(ref response) if idempotent && is_transient_status(response.status) => {
+                    self.policy.delay(attempt)
+                }
+                Err(ref e) if idempotent && is_transient(e) => self.policy.delay(attempt),
+                _ => return result,
+            };
+
+            match result {
+                Ok(ref response) => {
+                    warn!("{} {} returns {}, retry", request.method, request.url, response.status)
+                }
+                Err(ref e) => warn!("{} {} failed: {}, retry", request.method, request.url, e),
+            }
+
+            sleep(delay).await;
+            attempt += 1;
+        }
+    }
+}
+
+/// In-memory transport answering with canned responses, and recording every
+/// request it receives.
+///
+/// Routes are matched by method and url path, query string is ignored. Requests
+/// without a matching route get `404 Not Found`.
+pub struct FakeTransport {
+    routes: Vec<(Method, String, Response)>,
+    requests: RefCell<Vec<Request>>,
+}
+
+impl Default for FakeTransport {
+    fn default() -> FakeTransport {
+        FakeTransport::new()
+    }
+}
+
+impl FakeTransport {
+    pub fn new() -> FakeTransport {
+        FakeTransport {
+            routes: vec![],
+            requests: RefCell::new(vec![]),
+        }
+    }
+
+    /// Answer `method path` with `body`, later routes of the same path win.
+    pub fn route<P: AsRef<str>, B: AsRef<str>>(mut self,
+                                               method: Method,
+                                               path: P,
+                                               status: StatusCode,
+                                               body: B)
+                                               -> FakeTransport {
+        let response = Response::new(status, "", body);
+        self.routes.insert(0, (method, path.as_ref().to_owned(), response));
+        self
+    }
+
+    /// Answer `method path` with a full response, like one with `Set-Cookie`.
+    ///
+    /// An empty `response.url` is filled with the request url, set it to fake
+    /// a followed redirect.
+    pub fn route_response<P: AsRef<str>>(mut self,
+                                         method: Method,
+                                         path: P,
+                                         response: Response)
+                                         -> FakeTransport {
+        self.routes.insert(0, (method, path.as_ref().to_owned(), response));
+        self
+    }
+
+    /// Requests received so far.
+    pub fn requests(&self) -> Vec<Request> {
+        self.requests.borrow().clone()
+    }
+}
+
+#[async_trait(?Send)]
+impl Transport for FakeTransport {
+    async fn send(&self, request: &Request) -> Result<Response> {
+        self.requests.borrow_mut().push(request.clone());
+
+        let path = match Url::parse(&request.url) {
+            Ok(url) => url.path().to_owned(),
+            Err(_) => request.url.clone(),
+        };
+
+        for (method, route, response) in &self.routes {
+            if *method == request.method && *route == path {
+                let mut response = response.clone();
+                if response.url.is_empty() {
+                    response.url = request.url.clone();
+                }
+                return Ok(response);
+            }
+        }
+
+        debug!("no fake route for {} {}", request.method, path);
+        Ok(Response::new(StatusCode::NOT_FOUND, &request.url, ""))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::rc::Rc;
+    use std::time::{Duration, Instant};
+
+    use reqwest::header::{HeaderMap, HeaderValue, RETRY_AFTER};
+    use reqwest::{Method, StatusCode};
+
+    use super::{FakeTransport, Request, Response, RetryPolicy, RetryTransport, Transport};
+
+    const URL: &str = "https://tower.im/teams/";
+
+    fn policy(retries: u32) -> RetryPolicy {
+        RetryPolicy {
+            retries,
+            backoff: Duration::from_millis(20),
+        }
+    }
+
+    async fn send(method: Method, response: Response, retries: u32) -> (Response, usize) {
+        let fake = FakeTransport::new().route_response(method.clone(), "/teams/", response);
+        let fake = Rc::new(fake);
+        let transport = RetryTransport::new(fake.clone(), policy(retries));
+
+        let response = transport.send(&Request::new(method, URL, HeaderMap::new())).await.unwrap();
+        (response, fake.requests().len())
+    }
+
+    #[test]
+    fn backoff_doubles() {
+        let delays: Vec<u128> = (0..4).map(|x| policy(3).delay(x).as_millis()).collect();
+        assert_eq!(delays, vec![20, 40, 80, 160]);
+    }
+
+    #[tokio::test]
+    async fn get_retried_on_gateway_errors() {
+        for &status in &[StatusCode::BAD_GATEWAY,
+                         StatusCode::SERVICE_UNAVAILABLE,
+                         StatusCode::GATEWAY_TIMEOUT] {
+            let start = Instant::now();
+            let (response, sent) = send(Method::GET, Response::new(status, "", ""), 2).await;
+
+            assert_eq!(response.status, status);
+            assert_eq!(sent, 3);
+            // 20ms then 40ms
+            assert!(start.elapsed() >= Duration::from_millis(60));
+        }
+    }
+
+    #[tokio::test]
+    async fn get_not_retried_on_other_errors() {
+        for &status in &[StatusCode::NOT_FOUND, StatusCode::INTERNAL_SERVER_ERROR] {
+            let (_, sent) = send(Method::GET, Response::new(status, "", ""), 2).await;
+            assert_eq!(sent, 1);
+        }
+    }
+
+    #[tokio::test]
+    async fn post_not_retried() {
+        let response = Response::new(StatusCode::SERVICE_UNAVAILABLE, "", "");
+        let (response, sent) = send(Method::POST, response, 2).await;
+
+        assert_eq!(response.status, StatusCode::SERVICE_UNAVAILABLE);
+        assert_eq!(sent, 1);
+    }
+
+    #[tokio::test]
+    async fn too_many_requests_honours_retry_after() {
+        let mut response = Response::new(StatusCode::TOO_MANY_REQUESTS, "", "");
+        response.headers.insert(RETRY_AFTER, HeaderValue::from_static("1"));
+
+        // POSTs as well, the server did not process them
+        let start = Instant::now();
+        let (response, sent) = send(Method::POST, response, 1).await;
+
+        assert_eq!(response.status, StatusCode::TOO_MANY_REQUESTS);
+        assert_eq!(sent, 2);
+        assert!(start.elapsed() >= Duration::from_secs(1));
+    }
+}
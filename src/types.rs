@@ -0,0 +1,96 @@
+
+use crate::markdown::escape;
+
+/// A member of current team.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct Member {
+    pub guid: String,
+    pub nickname: String,
+}
+
+impl Member {
+    /// Html to @ this member in comments.
+    pub fn mention_html(&self) -> String {
+        format!("<a href=\"/members/{}\" data-mention=\"true\">@{}</a>",
+                escape(&self.guid),
+                escape(&self.nickname))
+    }
+}
+
+/// A field of the weekly report form.
+#[derive(Clone, Debug, PartialEq)]
+pub struct WeeklyField {
+    /// form input name
+    pub name: String,
+    /// form input value, identifies the question
+    pub value: String,
+    /// question shown to user
+    pub title: String,
+}
+
+impl WeeklyField {
+    /// Match the title of a section in a report file or the editor, ignoring
+    /// line breaks and repeated spaces.
+    pub fn matches<T: AsRef<str>>(&self, title: T) -> bool {
+        let words = |x: &str| x.split_whitespace().collect::<Vec<_>>().join(" ");
+        words(&self.title) == words(title.as_ref())
+    }
+}
+
+/// A filled section of a weekly report.
+#[derive(Clone, Debug, PartialEq)]
+pub struct WeeklyReportSection {
+    pub title: String,
+    /// html content
+    pub content: String,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct WeeklyReport {
+    pub year: i32,
+    pub week: String,
+    pub sections: Vec<WeeklyReportSection>,
+}
+
+impl WeeklyReport {
+    pub fn is_empty(&self) -> bool {
+        self.sections.is_empty()
+    }
+
+    /// Contents of every section, in order.
+    pub fn contents(&self) -> Vec<String> {
+        self.sections.iter().map(|x| x.content.clone()).collect()
+    }
+}
+
+/// A calendar event created on tower.im.
+#[derive(Clone, Debug, PartialEq)]
+pub struct CalendarEvent {
+    /// path of the event, like `/calendar_events/xxx`
+    pub url: String,
+}
+
+/// Weekly report and calendar of current member, fetched in one go.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Overview {
+    pub report: WeeklyReport,
+    /// raw calendar content, like `TowerClient::calendar_events`
+    pub calendar: String,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Member;
+
+    #[test]
+    fn mention_escapes_nickname() {
+        let member = Member {
+            guid: "mockmember0003".to_owned(),
+            nickname: "Alice & <b>Bob</b>".to_owned(),
+        };
+
+        assert_eq!(member.mention_html(),
+                   "<a href=\"/members/mockmember0003\" data-mention=\"true\">\
+                    @Alice &amp; &lt;b&gt;Bob&lt;/b&gt;</a>");
+    }
+}
@@ -0,0 +1,228 @@
+
+//! ISO-8601 weeks, which weekly reports of tower.im are filed by.
+//!
+//! Weeks start on monday and week 1 holds the first thursday of the year, so
+//! late december days may belong to week 1 of the next year and early january
+//! days to week 52 or 53 of the previous one. There is no week 0.
+
+use std::fmt;
+
+use time::{self, Tm};
+
+/// Where the current time comes from, so week calculation can be checked
+/// against any date.
+pub trait Clock {
+    fn now(&self) -> Tm;
+}
+
+/// The local time of the system.
+pub struct SystemClock;
+
+impl Clock for SystemClock {
+    fn now(&self) -> Tm {
+        time::now()
+    }
+}
+
+/// A clock stopped at a given date.
+pub struct FixedClock {
+    tm: Tm,
+}
+
+impl FixedClock {
+    /// Noon of `year-month-day`, month and day start from 1.
+    pub fn new(year: i32, month: u32, day: u32) -> FixedClock {
+        let mut tm = time::empty_tm();
+        tm.tm_year = year - 1900;
+        tm.tm_mon = month as i32 - 1;
+        tm.tm_mday = day as i32;
+        tm.tm_hour = 12;
+        tm.tm_wday = weekday(year, month, day) as i32 % 7;
+        tm.tm_yday = day_of_year(year, month, day) as i32 - 1;
+
+        FixedClock { tm }
+    }
+}
+
+impl Clock for FixedClock {
+    fn now(&self) -> Tm {
+        self.tm
+    }
+}
+
+/// An ISO week, like `2017-05`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub struct Week {
+    /// week-based year, may differ from the calendar year around new year
+    pub year: i32,
+    /// 1 to 52 or 53
+    pub week: u32,
+}
+
+impl Week {
+    /// Week `week` of `year`, `None` if the year has no such week.
+    pub fn new(year: i32, week: u32) -> Option<Week> {
+        if week < 1 || week > weeks_in_year(year) {
+            return None;
+        }
+
+        Some(Week {
+            year,
+            week,
+        })
+    }
+
+    /// Week containing `year-month-day`, month and day start from 1.
+    pub fn of_date(year: i32, month: u32, day: u32) -> Week {
+        let ordinal = day_of_year(year, month, day) as i32;
+        let week = (ordinal - weekday(year, month, day) as i32 + 10) / 7;
+
+        if week < 1 {
+            Week {
+                year: year - 1,
+                week: weeks_in_year(year - 1),
+            }
+        } else if week as u32 > weeks_in_year(year) {
+            Week {
+                year: year + 1,
+                week: 1,
+            }
+        } else {
+            Week {
+                year,
+                week: week as u32,
+            }
+        }
+    }
+
+    /// Week containing the date of `tm`.
+    pub fn of(tm: &Tm) -> Week {
+        Week::of_date(tm.tm_year + 1900, tm.tm_mon as u32 + 1, tm.tm_mday as u32)
+    }
+
+    pub fn current(clock: &dyn Clock) -> Week {
+        Week::of(&clock.now())
+    }
+
+    /// The week before the current one, for reports not filed in time.
+    pub fn last(clock: &dyn Clock) -> Week {
+        Week::current(clock).previous()
+    }
+
+    pub fn previous(&self) -> Week {
+        if self.week > 1 {
+            Week {
+                year: self.year,
+                week: self.week - 1,
+            }
+        } else {
+            Week {
+                year: self.year - 1,
+                week: weeks_in_year(self.year - 1),
+            }
+        }
+    }
+
+    pub fn next(&self) -> Week {
+        if self.week < weeks_in_year(self.year) {
+            Week {
+                year: self.year,
+                week: self.week + 1,
+            }
+        } else {
+            Week {
+                year: self.year + 1,
+                week: 1,
+            }
+        }
+    }
+
+    /// Two digit week number used in weekly report urls, like `05`.
+    pub fn number(&self) -> String {
+        format!("{:02}", self.week)
+    }
+}
+
+impl fmt::Display for Week {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}-{:02}", self.year, self.week)
+    }
+}
+
+fn is_leap_year(year: i32) -> bool {
+    (year % 4 == 0 && year % 100 != 0) || year % 400 == 0
+}
+
+// 1 for january 1st
+fn day_of_year(year: i32, month: u32, day: u32) -> u32 {
+    const DAYS_BEFORE: [u32; 12] = [0, 31, 59, 90, 120, 151, 181, 212, 243, 273, 304, 334];
+
+    let leap_day = if month > 2 && is_leap_year(year) { 1 } else { 0 };
+    DAYS_BEFORE[(month as usize - 1) % 12] + day + leap_day
+}
+
+// 1 for monday to 7 for sunday, Sakamoto's method
+fn weekday(year: i32, month: u32, day: u32) -> u32 {
+    const OFFSETS: [i32; 12] = [0, 3, 2, 5, 0, 3, 5, 1, 4, 6, 2, 4];
+
+    let y = if month < 3 { year - 1 } else { year };
+    let sunday_based = (y + y.div_euclid(4) - y.div_euclid(100) + y.div_euclid(400) +
+                        OFFSETS[(month as usize - 1) % 12] + day as i32)
+        .rem_euclid(7);
+
+    if sunday_based == 0 { 7 } else { sunday_based as u32 }
+}
+
+/// 53 when the year starts on a thursday, or a wednesday in leap years.
+pub fn weeks_in_year(year: i32) -> u32 {
+    // weekday of december 31st, 0 for sunday
+    let p = |y: i32| (y + y.div_euclid(4) - y.div_euclid(100) + y.div_euclid(400)).rem_euclid(7);
+
+    if p(year) == 4 || p(year - 1) == 3 { 53 } else { 52 }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn week(year: i32, week: u32) -> Week {
+        Week::new(year, week).unwrap()
+    }
+
+    #[test]
+    fn new_year_in_previous_year() {
+        assert_eq!(Week::current(&FixedClock::new(2016, 1, 1)), week(2015, 53));
+        assert_eq!(Week::current(&FixedClock::new(2021, 1, 3)), week(2020, 53));
+        assert_eq!(Week::current(&FixedClock::new(2017, 1, 1)), week(2016, 52));
+    }
+
+    #[test]
+    fn december_in_next_year() {
+        assert_eq!(Week::current(&FixedClock::new(2018, 12, 31)), week(2019, 1));
+        assert_eq!(Week::current(&FixedClock::new(2019, 12, 30)), week(2020, 1));
+    }
+
+    #[test]
+    fn last_week_across_new_year() {
+        assert_eq!(Week::last(&FixedClock::new(2016, 1, 4)), week(2015, 53));
+        assert_eq!(Week::last(&FixedClock::new(2019, 1, 7)), week(2019, 1));
+        assert_eq!(week(2015, 53).next(), week(2016, 1));
+    }
+
+    #[test]
+    fn weeks_of_years() {
+        assert_eq!(weeks_in_year(2015), 53);
+        assert_eq!(weeks_in_year(2016), 52);
+        assert_eq!(weeks_in_year(2020), 53);
+        assert_eq!(Week::new(2016, 53), None);
+        assert_eq!(Week::new(2016, 0), None);
+    }
+
+    #[test]
+    fn fixed_clock_weekday() {
+        // 2016-01-01 is a friday
+        assert_eq!(FixedClock::new(2016, 1, 1).now().tm_wday, 5);
+        assert_eq!(week(2017, 5).to_string(), "2017-05");
+        assert_eq!(week(2017, 5).number(), "05");
+    }
+}
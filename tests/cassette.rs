@@ -0,0 +1,47 @@
+
+//! Replay a cassette recorded from `tower-mock-server` with `--record`.
+//!
+//! The pages in it are the invented markup of the mock server, not captured
+//! from tower.im, so this checks recording and replaying only. It can't catch
+//! the scrapers falling behind changes of the real site.
+
+extern crate tokio;
+extern crate tower;
+
+use std::fs::File;
+use std::io::Read;
+
+use tower::cassette::ReplayTransport;
+use tower::week::Week;
+use tower::TowerClient;
+
+const CASSETTE: &str = concat!(env!("CARGO_MANIFEST_DIR"), "/tests/cassettes/login.json");
+
+#[test]
+fn secrets_redacted() {
+    let mut content = String::new();
+    File::open(CASSETTE).unwrap().read_to_string(&mut content).unwrap();
+
+    assert!(!content.contains("mock-sign-in-csrf-token"));
+    assert!(!content.contains("mock-csrf-token"));
+    assert!(!content.contains("sbw%40sbw.so"));
+    assert!(!content.contains("mockconnguid0001"));
+    assert!(content.contains(r#"name=\"authenticity_token\" value=\"REDACTED\""#));
+}
+
+#[tokio::test]
+async fn replay_login() {
+    let transport = ReplayTransport::load(CASSETTE).unwrap();
+    let mut client = TowerClient::with_transport(Box::new(transport), "https://tower.im");
+
+    // the conn_guid read from the cassette is `REDACTED` as well, and matched
+    client.login("sbw@sbw.so", "secret").await.unwrap();
+    assert_eq!(client.current_team(), "mockteam0001");
+    assert_eq!(client.cookie("remember_token").unwrap(), "REDACTED");
+    assert_eq!(client.member("张三").await.unwrap().guid, "mockmember0002");
+
+    let report = client.weekly_report(Week::new(2017, 18).unwrap()).await.unwrap();
+    assert_eq!(report.sections.len(), 3);
+    assert_eq!(report.sections[0].title, "本周完成的工作");
+    assert!(report.sections[0].content.contains("<li>chrome</li>"));
+}
@@ -0,0 +1,257 @@
+
+//! Run the `tower` binary against `tower::mock`, no network needed.
+
+extern crate serde_json;
+extern crate tower;
+extern crate url;
+
+use std::env::temp_dir;
+use std::fs::{create_dir_all, remove_dir_all, File};
+use std::io::{Read, Write};
+use std::path::PathBuf;
+use std::process::{Command, Output, Stdio};
+
+use serde_json::Value;
+
+use url::form_urlencoded;
+
+use tower::mock::{MockServer, RecordedRequest, REMEMBER_TOKEN, TEAM_GUID};
+
+const REPORT_PAGE: &str = "/members/mockmember0001/weekly_reports/2017-18/?conn_guid=\
+                           mockconnguid0001&pjax=1";
+const REPORT_URL: &str = "/members/mockmember0001/weekly_reports/2017-18";
+
+// a mock server and a home without browsers, config or session cache
+struct Fixture {
+    server: MockServer,
+    home: PathBuf,
+}
+
+impl Fixture {
+    fn new(name: &str) -> Fixture {
+        let home = temp_dir().join(format!("tower-cli-{}-{}", std::process::id(), name));
+        create_dir_all(&home).unwrap();
+
+        Fixture {
+            server: MockServer::start("127.0.0.1:0", None).unwrap(),
+            home,
+        }
+    }
+
+    fn command(&self, args: &[&str]) -> Command {
+        let mut command = Command::new(env!("CARGO_BIN_EXE_tower"));
+        command.args(args)
+            .env_clear()
+            .env("HOME", &self.home)
+            .env("XDG_CACHE_HOME", self.home.join("cache"))
+            .env("XDG_CONFIG_HOME", self.home.join("config"))
+            .env("TOWER_BASE_URL", self.server.url())
+            .stdin(Stdio::null());
+
+        command
+    }
+
+    // signed in with the cookies of the mock session
+    fn run(&self, args: &[&str]) -> Output {
+        let cookie = format!("remember_token={}; remember_team_guid={}", REMEMBER_TOKEN, TEAM_GUID);
+        self.command(args).env("TOWER_COOKIE", cookie).output().unwrap()
+    }
+
+    fn requests(&self, method: &str, path: &str) -> Vec<RecordedRequest> {
+        self.server
+            .requests()
+            .into_iter()
+            .filter(|x| x.method == method && x.path == path)
+            .collect()
+    }
+
+    fn close(self) {
+        self.server.close();
+        remove_dir_all(&self.home).unwrap();
+    }
+}
+
+fn stdout(output: &Output) -> String {
+    assert!(output.status.success(),
+            "{}{}",
+            String::from_utf8_lossy(&output.stdout),
+            String::from_utf8_lossy(&output.stderr));
+    String::from_utf8_lossy(&output.stdout).into_owned()
+}
+
+#[test]
+fn show_weekly_report() {
+    let fixture = Fixture::new("show");
+
+    let out = stdout(&fixture.run(&["--year", "2017", "--week", "18", "-w"]));
+    assert!(out.contains("本周完成的工作\n完成 **cookie** 读取\n\n- firefox\n- chrome\n"), "{}", out);
+    assert!(out.contains("下周计划\n写测试 & 修 bug\n"), "{}", out);
+
+    let requests = fixture.requests("GET", REPORT_PAGE);
+    assert_eq!(requests.len(), 1);
+    assert!(requests[0].header("cookie").unwrap().contains(REMEMBER_TOKEN));
+
+    fixture.close();
+}
+
+#[test]
+fn submit_weekly_report_from_markdown() {
+    let fixture = Fixture::new("submit");
+
+    let path = fixture.home.join("report.md");
+    File::create(&path)
+        .unwrap()
+        .write_all("## 本周完成的工作\n- 登录页 **done**\n\n## 下周计划\n发布\n\n## 需要的帮助\n"
+            .as_bytes())
+        .unwrap();
+
+    let args = ["--year", "2017", "--week", "18", "-y", "weekly", "submit", "--from"];
+    let mut args = args.to_vec();
+    args.push(path.to_str().unwrap());
+    let out = stdout(&fixture.run(&args));
+    assert!(out.contains("Post weekly report success."), "{}", out);
+
+    let posts = fixture.requests("POST", REPORT_URL);
+    assert_eq!(posts.len(), 1);
+    let form: Vec<(String, String)> = form_urlencoded::parse(posts[0].body.as_bytes())
+        .into_owned()
+        .collect();
+    assert_eq!(form[0], ("conn_guid".to_owned(), "mockconnguid0001".to_owned()));
+
+    let data: Value = serde_json::from_str(&form[1].1).unwrap();
+    assert_eq!(data[0]["question_guid"], "mockquestion0001");
+    assert_eq!(data[0]["content"], "<ul><li>登录页 <strong>done</strong></li></ul>");
+    assert_eq!(data[1]["content"], "<p>发布</p>");
+    assert_eq!(data[2]["content"], "");
+
+    fixture.close();
+}
+
+#[test]
+fn overtime_without_gio() {
+    let fixture = Fixture::new("overtime");
+
+    // no PATH, so gio can't be found
+    let output = fixture.run(&["-o", "--cc", "Alice & Bob"]);
+    assert!(stdout(&output).contains("/calendar_events/mockevent0001"));
+
+    let posts = fixture.requests("POST", "/calendar_events/mockevent0001/comments");
+    assert_eq!(posts.len(), 1);
+    let form: Vec<(String, String)> = form_urlencoded::parse(posts[0].body.as_bytes())
+        .into_owned()
+        .collect();
+    assert_eq!(form[1].1,
+               "<p><a href=\"/members/mockmember0003\" data-mention=\"true\">@Alice &amp; \
+                Bob</a></p>");
+    assert_eq!(form[3].1, "mockmember0003");
+
+    fixture.close();
+}
+
+#[test]
+fn login_with_password() {
+    let fixture = Fixture::new("login");
+
+    let output = fixture.command(&["login"])
+        .env("TOWER_EMAIL", "sbw@sbw.so")
+        .env("TOWER_PASSWORD", "secret & more")
+        .output()
+        .unwrap();
+    assert!(stdout(&output).contains("login success."));
+
+    let posts = fixture.requests("POST", "/users/sign_in");
+    assert_eq!(posts.len(), 1);
+    assert_eq!(posts[0].body,
+               "authenticity_token=mock-sign-in-csrf-token&email=sbw%40sbw.so&password=secret+%26+\
+                more&remember_me=1");
+
+    // the session is cached for the next run
+    let out = stdout(&fixture.command(&["--year", "2017", "--week", "18", "-w"]).output().unwrap());
+    assert!(out.contains("- firefox"), "{}", out);
+    assert_eq!(fixture.requests("POST", "/users/sign_in").len(), 1);
+
+    fixture.close();
+}
+
+#[test]
+fn new_member_not_in_cached_session() {
+    let fixture = Fixture::new("members");
+    stdout(&fixture.run(&["-c"]));
+
+    // a session cached before Alice & Bob joined
+    let path = fixture.home.join("cache/tower/session");
+    let mut content = String::new();
+    File::open(&path).unwrap().read_to_string(&mut content).unwrap();
+    let mut session: Value = serde_json::from_str(&content).unwrap();
+    session["members"]["value"].as_array_mut().unwrap().pop();
+    File::create(&path).unwrap().write_all(session.to_string().as_bytes()).unwrap();
+
+    let members = "/teams/mockteam0001/members/";
+    assert_eq!(fixture.requests("GET", members).len(), 1);
+    let output = fixture.command(&["-o", "--cc", "Alice & Bob"]).output().unwrap();
+    assert!(stdout(&output).contains("/calendar_events/mockevent0001"));
+    assert_eq!(fixture.requests("GET", members).len(), 2);
+
+    // and the fresh list is cached
+    let output = fixture.command(&["-o", "--cc", "Alice & Bob"]).output().unwrap();
+    stdout(&output);
+    assert_eq!(fixture.requests("GET", members).len(), 2);
+
+    fixture.close();
+}
+
+#[test]
+fn switched_team_not_cached() {
+    let fixture = Fixture::new("team");
+
+    stdout(&fixture.run(&["--team", "mockteam0002", "-c"]));
+    assert_eq!(fixture.requests("GET", "/teams/mockteam0002/members/").len(), 1);
+
+    // the next run starts from the default team again
+    let mut content = String::new();
+    File::open(fixture.home.join("cache/tower/session"))
+        .unwrap()
+        .read_to_string(&mut content)
+        .unwrap();
+    let session: Value = serde_json::from_str(&content).unwrap();
+    assert_eq!(session["tid"]["value"], "mockteam0001");
+
+    fixture.close();
+}
+
+#[test]
+fn not_logged_in() {
+    let fixture = Fixture::new("anonymous");
+
+    let output = fixture.command(&["-w"]).output().unwrap();
+    assert_eq!(output.status.code(), Some(2));
+    assert!(fixture.server.requests().is_empty());
+
+    fixture.close();
+}
+
+#[test]
+fn profile_not_found() {
+    let fixture = Fixture::new("profile");
+
+    let output = fixture.command(&["--profile", "nope", "-w"]).output().unwrap();
+    assert_eq!(output.status.code(), Some(7));
+    assert!(String::from_utf8_lossy(&output.stderr).contains("browser profile nope not found"));
+    assert!(fixture.server.requests().is_empty());
+
+    fixture.close();
+}
+
+#[test]
+fn invalid_timeout() {
+    let fixture = Fixture::new("timeout");
+
+    for args in &[["--timeout", "ten"], ["--connect-timeout", "0"]] {
+        let output = fixture.run(&args[..]);
+        assert!(!output.status.success());
+        assert!(String::from_utf8_lossy(&output.stderr).contains("invalid seconds"));
+    }
+    assert!(fixture.server.requests().is_empty());
+
+    fixture.close();
+}
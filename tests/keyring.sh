@@ -0,0 +1,11 @@
+#!/bin/sh
+# Run the keyring tests against a throwaway Secret Service on a private session
+# bus, the desktop keyring is never touched. Needs dbus-run-session and
+# gnome-keyring-daemon.
+set -e
+cd "$(dirname "$0")/.."
+
+exec dbus-run-session -- sh -c '
+    printf "" | gnome-keyring-daemon --unlock --components=secrets >/dev/null
+    TOWER_KEYRING_STAND_IN=1 cargo test --features keyring --lib keyring -- --ignored
+'